@@ -0,0 +1,184 @@
+use crate::config::{Config, FixupMode};
+use crate::diff::{build_patch, parse_diff, FileDiff};
+use crate::errors::*;
+use crate::git::*;
+use crate::plan::print_plan;
+use crate::protect::guard_protected;
+use crate::rebase::rebase;
+use crate::todo::{parse_fixup_subject, resolve_fixup_targets, TodoAction};
+use crate::undo::record_smash_state;
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+struct Assignment {
+    target: String,
+    hunks: Vec<(usize, usize)>,
+}
+
+/// Assigns every staged hunk to the single commit its pre-image lines blame
+/// to, pending fixup commits count as the commit they fix up. Hunks that
+/// blame to multiple commits or to nothing inside the range are left
+/// unassigned.
+fn assign_hunks(
+    files: &[FileDiff],
+    range: &str,
+    blame_options: &[&str],
+) -> Result<(Vec<Assignment>, usize)> {
+    let mut blamed = Vec::new();
+    let mut unassigned = 0;
+
+    for (file_index, file_diff) in files.iter().enumerate() {
+        let file = match (file_diff.is_modification(), &file_diff.old_file) {
+            (true, Some(file)) => file,
+            _ => {
+                unassigned += file_diff.hunks.len().max(1);
+                continue;
+            }
+        };
+
+        let line_count = git_line_count("HEAD", file)?;
+        for (hunk_index, hunk) in file_diff.hunks.iter().enumerate() {
+            let commits = match hunk.blame_range(line_count) {
+                Some(blame_range) => git_blame(range, file, &[blame_range], blame_options)?
+                    .into_iter()
+                    .filter_map(|(_, commit)| commit)
+                    .collect::<HashSet<_>>(),
+                None => HashSet::new(),
+            };
+            blamed.push(((file_index, hunk_index), commits));
+        }
+    }
+
+    let commits = blamed
+        .iter()
+        .flat_map(|(_, commits)| commits.iter().cloned())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let subjects = git_commit_subjects(&commits).context("failed to get blamed subjects")?;
+    let targets = match subjects
+        .iter()
+        .any(|e| parse_fixup_subject(&e.subject).0 != TodoAction::Pick)
+    {
+        true => resolve_fixup_targets(
+            &git_range_commits(range).with_context(|| format!("failed to list {}", range))?,
+        ),
+        false => Vec::new(),
+    };
+
+    let mut assignments: Vec<Assignment> = Vec::new();
+    for (hunk, commits) in blamed {
+        // Fixups without a target in the range leave the hunk unassigned
+        let commits = commits
+            .into_iter()
+            .map(|commit| match targets.iter().find(|(e, _)| *e == commit) {
+                Some((_, target)) => target.clone(),
+                None => Some(commit),
+            })
+            .collect::<HashSet<_>>();
+        let target = match (commits.len(), commits.into_iter().next()) {
+            (1, Some(Some(target))) => target,
+            _ => {
+                unassigned += 1;
+                continue;
+            }
+        };
+
+        match assignments.iter_mut().find(|e| e.target == target) {
+            Some(assignment) => assignment.hunks.push(hunk),
+            None => assignments.push(Assignment {
+                target,
+                hunks: vec![hunk],
+            }),
+        }
+    }
+
+    Ok((assignments, unassigned))
+}
+
+pub fn absorb(config: &Config, staged_files: &[String], range: &str) -> Result<()> {
+    let diff = git_diff_changes(0, staged_files, config)?;
+    let files = parse_diff(&diff)?;
+
+    let (assignments, unassigned) = assign_hunks(&files, range, &config.blame_options())?;
+    if assignments.is_empty() {
+        bail!("No staged hunk could be assigned to a single commit\nUse git smash to pick a target manually");
+    }
+
+    if config.dry_run {
+        for assignment in &assignments {
+            writeln!(
+                io::stdout(),
+                "{} hunk(s) into {}",
+                assignment.hunks.len(),
+                assignment.target
+            )?;
+        }
+        let targets = assignments
+            .into_iter()
+            .map(|e| e.target)
+            .collect::<Vec<_>>();
+        return print_plan(&targets, FixupMode::Fixup, config);
+    }
+
+    for assignment in &assignments {
+        guard_protected(&assignment.target, config)?;
+    }
+
+    record_smash_state("absorb")?;
+
+    let tree = git_write_tree().context("failed to write index tree")?;
+    let mut committed = HashSet::new();
+    let mut fixups = Vec::new();
+    let result = assignments.iter().try_for_each(|assignment| -> Result<()> {
+        git_read_tree("HEAD").context("failed to reset index to HEAD")?;
+        let patch = build_patch(&files, &assignment.hunks, &committed);
+        git_apply_cached(&patch)
+            .with_context(|| format!("failed to stage hunks for {}", assignment.target))?;
+        git_commit_fixup(
+            &assignment.target,
+            FixupMode::Fixup,
+            &config.gpg_sign_option,
+            &config.verify_option,
+        )?;
+        fixups.push(git_rev_parse("HEAD").context("failed to rev parse HEAD")?);
+        committed.extend(assignment.hunks.iter().copied());
+        Ok(())
+    });
+    // The original index always equals HEAD plus everything not yet committed,
+    // with all changes as input the rest simply stays in the working tree
+    let index = match config.all_changes {
+        true => "HEAD",
+        false => &tree,
+    };
+    git_read_tree(index).context("failed to restore index")?;
+    result?;
+
+    if unassigned > 0 {
+        writeln!(
+            io::stderr(),
+            "{} hunk(s) could not be assigned to a single commit and remain {}",
+            unassigned,
+            match config.all_changes {
+                true => "in the working tree",
+                false => "staged",
+            }
+        )
+        .ok();
+    }
+
+    if config.auto_rebase {
+        let targets = assignments
+            .into_iter()
+            .map(|e| e.target)
+            .collect::<Vec<_>>();
+        let oldest =
+            git_merge_base_octopus(&targets).context("failed to find the oldest target commit")?;
+        // The unassigned hunks stay staged
+        let restore = (!config.all_changes).then_some(tree.as_str());
+        rebase(&oldest, &fixups, restore, config)?;
+    }
+
+    Ok(())
+}
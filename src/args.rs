@@ -16,6 +16,14 @@ pub struct Args {
     #[arg(long, group = "mode")]
     pub select: bool,
     /// Git log format to pretty print the targets
+    ///
+    /// Besides the git log placeholders the format understands
+    /// %(smash:source) for the labels of the sources that listed the commit,
+    /// %(smash:blame-lines) for the number of staged lines blaming to it,
+    /// %(smash:files) for the number of staged files it touched,
+    /// %(smash:branches) for the local branches a rebase would carry along
+    /// and %(smash:conflict) to mark targets whose rebase would conflict,
+    /// which simulates a rebase per listed commit.
     #[arg(long)]
     pub format: Option<String>,
     /// Limit number of listed commits (0 for unlimited stream)
@@ -33,6 +41,39 @@ pub struct Args {
     /// Do not rebase the fixup commit into the target
     #[arg(long, group = "autorebase")]
     pub no_rebase: bool,
+    /// Smash all changes of tracked files instead of only the staged ones
+    #[arg(long, short = 'A')]
+    pub all_changes: bool,
+    /// Include untracked files in --all-changes
+    #[arg(long, requires = "all_changes")]
+    pub include_untracked: bool,
+    /// Interactively pick the unstaged hunks to smash, annotated with blame
+    #[arg(long, short = 'p')]
+    pub patch: bool,
+    /// Print the fixup commit and rebase todo without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Move branches pointing at rebased commits along with the rebase
+    #[arg(long, group = "rebase_update_refs")]
+    pub update_refs: bool,
+    /// Leave branches pointing at rebased commits behind
+    #[arg(long, group = "rebase_update_refs")]
+    pub no_update_refs: bool,
+    /// Squash all pending fixup commits of the rebased range
+    #[arg(long, group = "pending_fixups")]
+    pub squash_pending: bool,
+    /// Only squash the newly created fixup commit
+    #[arg(long, group = "pending_fixups")]
+    pub no_squash_pending: bool,
+    /// Rewrite targets that are already contained in a protected ref
+    #[arg(long)]
+    pub allow_protected: bool,
+    /// Refuse to smash into targets whose rebase would conflict
+    #[arg(long, group = "require_clean_rebase")]
+    pub require_clean: bool,
+    /// Smash even if the rebase is predicted to conflict
+    #[arg(long, group = "require_clean_rebase")]
+    pub no_require_clean: bool,
     /// Let the user edit the list of commits before rebasing
     #[arg(long, short = 'i')]
     pub interactive: bool,
@@ -48,9 +89,48 @@ pub struct Args {
     /// Do not list commits acquired from history of changed files
     #[arg(long, group = "list_files")]
     pub no_files: bool,
+    /// List commits related to added and deleted files
+    #[arg(long, group = "list_lifecycle")]
+    pub lifecycle: bool,
+    /// Do not list commits related to added and deleted files
+    #[arg(long, group = "list_lifecycle")]
+    pub no_lifecycle: bool,
+    /// List commits that last changed the functions of staged hunks
+    #[arg(long, group = "list_functions")]
+    pub functions: bool,
+    /// Do not list commits that last changed the functions of staged hunks
+    #[arg(long, group = "list_functions")]
+    pub no_functions: bool,
+    /// List commits that introduced or last changed removed identifiers
+    #[arg(long, group = "list_pickaxe")]
+    pub pickaxe: bool,
+    /// Do not list commits that introduced or last changed removed identifiers
+    #[arg(long, group = "list_pickaxe")]
+    pub no_pickaxe: bool,
+    /// List commits mentioning the ticket key of the branch name
+    #[arg(long, group = "list_tickets")]
+    pub tickets: bool,
+    /// Do not list commits mentioning the ticket key of the branch name
+    #[arg(long, group = "list_tickets")]
+    pub no_tickets: bool,
     /// List commits acquired from recent history (default 0)
     #[arg(long, value_name = "number")]
     pub recent: Option<u32>,
+    /// Sort the listed commits by how likely they are the target
+    #[arg(long, group = "rank_targets")]
+    pub rank: bool,
+    /// List commits in the order of their sources
+    #[arg(long, group = "rank_targets")]
+    pub no_rank: bool,
+    /// Smash into the dominant blamed commit without opening the menu
+    #[arg(long, group = "auto_target")]
+    pub auto: bool,
+    /// Always let the user pick the target from the menu
+    #[arg(long, group = "auto_target")]
+    pub no_auto: bool,
+    /// Share of blamed lines in percent a commit needs for --auto (default 80)
+    #[arg(long, value_name = "percent")]
+    pub auto_threshold: Option<u32>,
     /// Limit the listed commits to the given range
     #[arg(long, group = "rev_range", value_name = "revision-range")]
     pub range: Option<String>,
@@ -86,19 +166,49 @@ pub struct Args {
     #[arg(long, group = "git_pager")]
     pub no_pager: bool,
 
+    /// Menu command used to select the target
+    ///
+    /// Use builtin for the terminal menu if built with the tui feature. The
+    /// arguments given in smash.menuArgs are passed to this command instead
+    /// of its known defaults and may use %(smash:preview) for the command
+    /// previewing a target and %(smash:preview-window) for the layout of
+    /// --preview-window.
+    #[arg(long, value_name = "command")]
+    pub menu: Option<String>,
+
     /// Determines the layout of the fzf preview window
     #[arg(long, value_name = "layout")]
     pub preview_window: Option<String>,
 
+    /// Continue a smash that stopped on rebase conflicts
+    #[arg(long = "continue", group = "sequencer")]
+    pub continue_smash: bool,
+    /// Abort a stopped smash and restore the state before it
+    #[arg(long, group = "sequencer")]
+    pub abort: bool,
+
     /// Target commit to smash into
     pub commit: Option<String>,
 
+    /// Only smash the changes below the given paths
+    #[arg(last = true, value_name = "pathspec")]
+    pub pathspecs: Vec<String>,
+
     #[command(subcommand)]
     pub subcommand: Option<SubCommand>,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum SubCommand {
+    /// Smash each staged hunk into the commit it blames to
+    #[clap(name = "absorb")]
+    Absorb,
+    /// Squash all pending fixup commits of the range with a single rebase
+    #[clap(name = "rebase")]
+    Rebase,
+    /// Restore branch, HEAD and index to the state before the last smash
+    #[clap(name = "undo")]
+    Undo,
     /// Generate shell completions
     #[clap(name = "completions")]
     Completions(Completions),
@@ -0,0 +1,1004 @@
+use crate::config::{Config, ExternalSource};
+use crate::diff::{parse_diff, FileDiff};
+use crate::errors::*;
+use crate::git::*;
+use crate::hash::*;
+use crate::predict::predict_conflict;
+use crate::todo::{parse_fixup_subject, resolve_fixup_targets, TodoAction};
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasherDefault;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use ahash::RandomState;
+use regex::Regex;
+
+const WEIGHT_BLAME: f64 = 4.0;
+const WEIGHT_FILES: f64 = 2.0;
+const WEIGHT_RECENCY: f64 = 1.0;
+const WEIGHT_SOURCES: f64 = 1.0;
+
+/// Number of commits each added or deleted file heuristic contributes.
+const LIFECYCLE_COMMITS: u32 = 3;
+/// Number of commits that last changed a function listed per function.
+const FUNCTION_COMMITS: u32 = 3;
+/// Number of removed identifiers searched for with the pickaxe.
+const PICKAXE_IDENTIFIERS: usize = 5;
+/// Identifiers shorter than this are too common to search for.
+const PICKAXE_MIN_LENGTH: usize = 4;
+
+/// Smash placeholders with the control character they are replaced by while
+/// git renders the rest of the format.
+const PLACEHOLDERS: [(&str, char, &str); 5] = [
+    ("%(smash:source)", '\x01', "%x01"),
+    ("%(smash:blame-lines)", '\x02', "%x02"),
+    ("%(smash:files)", '\x03', "%x03"),
+    (CONFLICT_PLACEHOLDER, '\x04', "%x04"),
+    (BRANCHES_PLACEHOLDER, '\x05', "%x05"),
+];
+const CONFLICT_PLACEHOLDER: &str = "%(smash:conflict)";
+const BRANCHES_PLACEHOLDER: &str = "%(smash:branches)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Source {
+    Recent,
+    Blame,
+    Files,
+    Lifecycle,
+    Functions,
+    Pickaxe,
+    Tickets,
+    External(usize),
+}
+
+impl Source {
+    pub fn label<'a>(&self, config: &'a Config) -> &'a str {
+        match self {
+            Self::Recent => &config.source_label_recent,
+            Self::Blame => &config.source_label_blame,
+            Self::Files => &config.source_label_files,
+            Self::Lifecycle => &config.source_label_lifecycle,
+            Self::Functions => &config.source_label_functions,
+            Self::Pickaxe => &config.source_label_pickaxe,
+            Self::Tickets => &config.source_label_tickets,
+            Self::External(index) => &config.external_sources[*index].label,
+        }
+    }
+}
+
+pub struct Candidate {
+    pub commit: String,
+    pub sources: Vec<Source>,
+    pub blame_lines: u32,
+    pub files: u32,
+    pub score: f64,
+    pub conflict: bool,
+    pub branches: Vec<String>,
+}
+
+impl Candidate {
+    const fn new(commit: String) -> Self {
+        Self {
+            commit,
+            sources: Vec::new(),
+            blame_lines: 0,
+            files: 0,
+            score: 0.0,
+            conflict: false,
+            branches: Vec::new(),
+        }
+    }
+}
+
+/// Candidates merged across all sources in the order they were found.
+pub struct Candidates {
+    hasher: RandomState,
+    index: HashMap<u64, usize, BuildHasherDefault<IdentityHasher>>,
+    candidates: Vec<Candidate>,
+}
+
+impl Candidates {
+    pub fn new() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            index: HashMap::default(),
+            candidates: Vec::new(),
+        }
+    }
+
+    fn entry(&mut self, commit: &str) -> &mut Candidate {
+        let key = hash(&self.hasher, commit);
+        let index = *self.index.entry(key).or_insert_with(|| {
+            self.candidates.push(Candidate::new(commit.to_string()));
+            self.candidates.len() - 1
+        });
+        &mut self.candidates[index]
+    }
+
+    pub fn add(&mut self, commit: &str, source: Source) -> &mut Candidate {
+        let candidate = self.entry(commit);
+        if !candidate.sources.contains(&source) {
+            candidate.sources.push(source);
+        }
+        candidate
+    }
+
+    /// Redirects the candidates that are pending fixup commits to the commit
+    /// they fix up, merging them into it, and drops those without a target.
+    pub fn redirect(self, targets: &[(String, Option<String>)]) -> Self {
+        let mut candidates = Self::new();
+        for candidate in self.candidates {
+            let commit = match targets.iter().find(|(e, _)| *e == candidate.commit) {
+                Some((_, Some(target))) => target,
+                Some((_, None)) => continue,
+                None => &candidate.commit,
+            };
+            let entry = candidates.entry(commit);
+            for source in candidate.sources {
+                if !entry.sources.contains(&source) {
+                    entry.sources.push(source);
+                }
+            }
+            entry.blame_lines += candidate.blame_lines;
+            entry.files = entry.files.max(candidate.files);
+        }
+        candidates
+    }
+
+    pub fn commits(&self) -> Vec<String> {
+        self.candidates.iter().map(|e| e.commit.clone()).collect()
+    }
+
+    /// Scores every candidate by the share of staged lines blaming to it, the
+    /// share of staged files it touched, its recency and the number of sources
+    /// that agreed on it, then sorts by descending score.
+    pub fn rank(&mut self, staged_files: usize, times: &[(String, i64)]) {
+        let total_blame_lines = self.candidates.iter().map(|e| e.blame_lines).sum::<u32>();
+
+        let mut by_time = times.iter().collect::<Vec<_>>();
+        by_time.sort_by_key(|(_, time)| Reverse(*time));
+        let recency = by_time
+            .iter()
+            .enumerate()
+            .map(|(rank, (commit, _))| (commit.as_str(), 1.0 / (1.0 + rank as f64)))
+            .collect::<HashMap<_, _>>();
+
+        for candidate in &mut self.candidates {
+            let blame = match total_blame_lines {
+                0 => 0.0,
+                total => f64::from(candidate.blame_lines) / f64::from(total),
+            };
+            let files = match staged_files {
+                0 => 0.0,
+                total => f64::from(candidate.files) / total as f64,
+            };
+            let recency = recency
+                .get(candidate.commit.as_str())
+                .copied()
+                .unwrap_or_default();
+            let sources = candidate.sources.len().saturating_sub(1) as f64;
+
+            candidate.score = WEIGHT_SOURCES.mul_add(
+                sources,
+                WEIGHT_RECENCY.mul_add(recency, WEIGHT_FILES.mul_add(files, WEIGHT_BLAME * blame)),
+            );
+        }
+
+        // Stable sort keeps the source order for equally scored candidates
+        self.candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    }
+
+    pub fn into_vec(self) -> Vec<Candidate> {
+        self.candidates
+    }
+}
+
+pub fn collect_candidates(
+    config: &Config,
+    staged_files: &[String],
+    range: &str,
+) -> Result<Vec<Candidate>> {
+    let mut candidates = Candidates::new();
+
+    if config.recent > 0 {
+        for rev in git_rev_list(range, config.recent)
+            .with_context(|| format!("failed to get rev-list for {}", range))?
+        {
+            candidates.add(&rev, Source::Recent);
+        }
+    }
+
+    if config.blame {
+        for rev in get_commits_from_blame(config, staged_files, range)? {
+            candidates.add(&rev, Source::Blame).blame_lines += 1;
+        }
+    }
+
+    if config.files {
+        for (rev, files) in
+            git_file_revs(staged_files, range, config.max_count, config.follow_renames)
+                .context("failed to get history of staged files")?
+        {
+            candidates.add(&rev, Source::Files).files = files;
+        }
+    }
+
+    if config.functions {
+        for rev in get_commits_from_functions(config, staged_files, range)? {
+            candidates.add(&rev, Source::Functions);
+        }
+    }
+
+    if config.pickaxe {
+        for rev in get_commits_from_pickaxe(config, staged_files, range)
+            .context("failed to search for removed identifiers")?
+        {
+            candidates.add(&rev, Source::Pickaxe);
+        }
+    }
+
+    if config.lifecycle {
+        for rev in get_commits_from_lifecycle(config, staged_files, range)
+            .context("failed to get commits related to added and deleted files")?
+        {
+            candidates.add(&rev, Source::Lifecycle);
+        }
+    }
+
+    if config.tickets {
+        for rev in get_commits_from_tickets(config, range)
+            .context("failed to get commits mentioning the ticket of the branch")?
+        {
+            candidates.add(&rev, Source::Tickets);
+        }
+    }
+
+    for (index, source) in config.external_sources.iter().enumerate() {
+        for rev in get_commits_from_external(source, staged_files, range)
+            .with_context(|| format!("failed to get commits from source {}", source.name))?
+        {
+            candidates.add(&rev, Source::External(index));
+        }
+    }
+
+    let commits = candidates.commits();
+    let subjects = git_commit_subjects(&commits).context("failed to get candidate subjects")?;
+    if subjects
+        .iter()
+        .any(|e| parse_fixup_subject(&e.subject).0 != TodoAction::Pick)
+    {
+        let targets = resolve_fixup_targets(
+            &git_range_commits(range).with_context(|| format!("failed to list {}", range))?,
+        );
+        candidates = candidates.redirect(&targets);
+    }
+
+    if config.rank {
+        let times = git_commit_times(&candidates.commits())
+            .context("failed to get commit times of candidates")?;
+        candidates.rank(staged_files.len(), &times);
+    }
+
+    Ok(candidates.into_vec())
+}
+
+/// Returns the candidate that holds at least the threshold percentage of all
+/// blamed staged lines, if any.
+pub fn auto_target(candidates: &[Candidate], threshold: u32) -> Option<&Candidate> {
+    let total_blame_lines = candidates.iter().map(|e| e.blame_lines).sum::<u32>();
+    if total_blame_lines == 0 {
+        return None;
+    }
+    candidates
+        .iter()
+        .max_by_key(|e| e.blame_lines)
+        .filter(|e| e.blame_lines * 100 >= threshold.min(100) * total_blame_lines)
+}
+
+/// Predicts for every candidate whether smashing into it would conflict, this
+/// simulates a rebase per candidate and is only done if the format shows it.
+pub fn mark_conflicts(candidates: &mut [Candidate], config: &Config) -> Result<()> {
+    if !config.format.contains(CONFLICT_PLACEHOLDER) {
+        return Ok(());
+    }
+    for candidate in candidates {
+        candidate.conflict = predict_conflict(&candidate.commit, config.fixup_mode, config)
+            .with_context(|| format!("failed to predict conflicts of {}", candidate.commit))?
+            .is_some();
+    }
+    Ok(())
+}
+
+/// Looks up the other local branches a rebase from every candidate would
+/// carry along, only done if the format shows them.
+pub fn mark_branches(candidates: &mut [Candidate], config: &Config) -> Result<()> {
+    if !config.format.contains(BRANCHES_PLACEHOLDER) {
+        return Ok(());
+    }
+    let branches = git_merged_branches().context("failed to list merged branches")?;
+    if branches.is_empty() || candidates.is_empty() {
+        return Ok(());
+    }
+
+    // Walk the history above the candidates once instead of asking git for
+    // the branches containing every single candidate
+    let commits = candidates
+        .iter()
+        .map(|e| e.commit.clone())
+        .collect::<Vec<_>>();
+    let base = git_merge_base_octopus(&commits).ok();
+    let tips = branches.iter().map(|(e, _)| e.clone()).collect::<Vec<_>>();
+    let parents = git_rev_parents(&tips, base.as_deref())
+        .context("failed to walk the history of merged branches")?;
+
+    for (tip, name) in &branches {
+        let mut reachable = HashSet::new();
+        let mut stack = vec![tip.as_str()];
+        while let Some(commit) = stack.pop() {
+            if reachable.insert(commit) {
+                stack.extend(
+                    parents
+                        .get(commit)
+                        .into_iter()
+                        .flatten()
+                        .map(|e| e.as_str()),
+                );
+            }
+        }
+        let name = name.strip_prefix("refs/heads/").unwrap_or(name);
+        for candidate in candidates.iter_mut() {
+            if reachable.contains(candidate.commit.as_str()) {
+                candidate.branches.push(name.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_commits_from_blame(
+    config: &Config,
+    staged_files: &[String],
+    range: &str,
+) -> Result<Vec<String>> {
+    // Without context only the touched lines count, like absorb assigns them
+    let diff = git_diff_changes(0, staged_files, config)?;
+
+    let mut commits: Vec<String> = Vec::new();
+
+    for file_diff in parse_diff(&diff)? {
+        let file = match file_diff.old_file {
+            Some(file) => file,
+            None => continue,
+        };
+
+        let line_count = git_line_count("HEAD", &file)?;
+        let ranges = file_diff
+            .hunks
+            .iter()
+            .filter_map(|hunk| hunk.blame_range(line_count))
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            continue;
+        }
+
+        for (_, commit) in git_blame(range, &file, &ranges, &config.blame_options())? {
+            if let Some(commit) = commit {
+                commits.push(commit);
+            }
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Blame only finds the exact lines, so also list the commits that last
+/// changed the functions the staged hunks are in. Functions git can't find
+/// in the file are skipped.
+fn get_commits_from_functions(
+    config: &Config,
+    staged_files: &[String],
+    range: &str,
+) -> Result<Vec<String>> {
+    let diff = git_diff_changes(0, staged_files, config)?;
+
+    let mut commits = Vec::new();
+    for file_diff in parse_diff(&diff)? {
+        let file = match (file_diff.is_modification(), &file_diff.old_file) {
+            (true, Some(file)) => file,
+            _ => continue,
+        };
+
+        let mut functions = Vec::new();
+        for function in file_diff.hunks.iter().filter_map(|e| e.function_name()) {
+            if functions.contains(&function) {
+                continue;
+            }
+            functions.push(function);
+
+            let line_range = format!(":{}:{}", function, file);
+            if let Ok(revs) = git_log_revs(
+                range,
+                &["-L", &line_range, "--no-patch"],
+                &[],
+                FUNCTION_COMMITS,
+            ) {
+                commits.extend(revs);
+            }
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Identifiers on removed lines that the added lines no longer contain, like
+/// the old name of a renamed symbol, most frequent first.
+fn removed_identifiers(files: &[FileDiff]) -> Result<Vec<String>> {
+    let re_identifier = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*")?;
+    let identifiers = |prefix: char| {
+        let mut identifiers: Vec<(&str, usize)> = Vec::new();
+        for line in files
+            .iter()
+            .flat_map(|e| &e.hunks)
+            .flat_map(|e| &e.lines)
+            .filter_map(|e| e.strip_prefix(prefix))
+        {
+            for identifier in re_identifier.find_iter(line).map(|e| e.as_str()) {
+                match identifiers.iter_mut().find(|(e, _)| *e == identifier) {
+                    Some((_, count)) => *count += 1,
+                    None => identifiers.push((identifier, 1)),
+                }
+            }
+        }
+        identifiers
+    };
+
+    let added = identifiers('+');
+    let mut removed = identifiers('-')
+        .into_iter()
+        .filter(|(e, _)| e.len() >= PICKAXE_MIN_LENGTH)
+        .filter(|(e, _)| !added.iter().any(|(added, _)| added == e))
+        .collect::<Vec<_>>();
+    // Stable sort keeps the diff order for equally frequent identifiers
+    removed.sort_by_key(|(_, count)| Reverse(*count));
+    Ok(removed
+        .into_iter()
+        .take(PICKAXE_IDENTIFIERS)
+        .map(|(e, _)| e.to_string())
+        .collect())
+}
+
+/// Searches the range for the commits that introduced and last changed the
+/// identifiers the staged changes remove, which finds the origin of renamed
+/// symbols in other files that neither blame nor file history find.
+fn get_commits_from_pickaxe(
+    config: &Config,
+    staged_files: &[String],
+    range: &str,
+) -> Result<Vec<String>> {
+    let diff = git_diff_changes(0, staged_files, config)?;
+
+    let mut commits = Vec::new();
+    for identifier in removed_identifiers(&parse_diff(&diff)?)? {
+        let pickaxe = format!("-S{}", identifier);
+        let revs = git_log_revs(range, &[&pickaxe], &[], 0)?;
+        if let Some(last) = revs.first() {
+            commits.push(last.to_string());
+        }
+        if let Some(introduced) = revs.iter().skip(1).last() {
+            commits.push(introduced.to_string());
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Extracts the distinct ticket keys matching the pattern from a branch name.
+fn ticket_keys(pattern: &str, branch: &str) -> Result<Vec<String>> {
+    let re_ticket = Regex::new(pattern)
+        .with_context(|| format!("failed to parse smash.ticketPattern '{}'", pattern))?;
+    let mut keys: Vec<String> = Vec::new();
+    for key in re_ticket.find_iter(branch).map(|e| e.as_str()) {
+        if !key.is_empty() && !keys.iter().any(|e| e == key) {
+            keys.push(key.to_string());
+        }
+    }
+    Ok(keys)
+}
+
+/// Regex matching the ticket key as a whole word, so ABC-12 does not match
+/// ABC-123.
+fn ticket_regex(key: &str) -> Result<Regex> {
+    Ok(Regex::new(&format!(
+        "(^|[^[:alnum:]]){}([^[:alnum:]]|$)",
+        regex::escape(key)
+    ))?)
+}
+
+/// Lists the commits whose subject or trailers mention a ticket key found in
+/// the name of the current branch.
+fn get_commits_from_tickets(config: &Config, range: &str) -> Result<Vec<String>> {
+    let branch = match git_symbolic_head()? {
+        Some(branch) => branch,
+        None => return Ok(Vec::new()),
+    };
+    let branch = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+
+    let keys = ticket_keys(&config.ticket_pattern, branch)?
+        .iter()
+        .map(|e| ticket_regex(e))
+        .collect::<Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut commits = git_subjects_trailers(range)?
+        .into_iter()
+        .filter(|(_, subject, trailers)| {
+            keys.iter()
+                .any(|e| e.is_match(subject) || e.is_match(trailers))
+        })
+        .map(|(commit, _, _)| commit)
+        .collect::<Vec<_>>();
+    if config.max_count > 0 {
+        commits.truncate(config.max_count as usize);
+    }
+    Ok(commits)
+}
+
+/// Runs the command of a user defined source with the staged files on stdin
+/// and the range in GIT_SMASH_RANGE, it prints one commit per line. Commits
+/// that don't resolve or lie outside the range are skipped with a warning.
+fn get_commits_from_external(
+    source: &ExternalSource,
+    staged_files: &[String],
+    range: &str,
+) -> Result<Vec<String>> {
+    let mut cmd = Command::new("sh")
+        .args(["-c", &source.command])
+        .env("GIT_SMASH_RANGE", range)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run '{}'", source.command))?;
+    // Feed the files while the output is read, a command printing before it
+    // consumed its input would otherwise block on a full pipe. Commands that
+    // don't care about the files may exit without reading them
+    let stdin = cmd.stdin.take();
+    let files = staged_files.join("\n");
+    let writer = thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            writeln!(stdin, "{}", files).ok();
+        }
+    });
+    let output = cmd.wait_with_output()?;
+    writer.join().ok();
+    if !output.status.success() {
+        bail!("'{}' exited with {}", source.command, output.status);
+    }
+
+    let revs = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|e| e.split_whitespace().next())
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+    if revs.is_empty() {
+        return Ok(revs);
+    }
+
+    let resolved = git_resolve_commits(&revs)?;
+    let range_commits = git_range_contains(
+        range,
+        &resolved
+            .iter()
+            .filter_map(|(_, commit)| commit.clone())
+            .collect::<Vec<_>>(),
+    )
+    .with_context(|| format!("failed to check the commits are part of {}", range))?;
+    let mut commits = Vec::new();
+    for (rev, commit) in resolved {
+        match commit {
+            Some(commit) if range_commits.contains(&commit) => commits.push(commit),
+            Some(_) => {
+                writeln!(
+                    io::stderr(),
+                    "Source {} suggested {} outside of {}, skipping it",
+                    source.name,
+                    rev,
+                    range
+                )
+                .ok();
+            }
+            None => {
+                writeln!(
+                    io::stderr(),
+                    "Source {} suggested unknown commit {}, skipping it",
+                    source.name,
+                    rev
+                )
+                .ok();
+            }
+        }
+    }
+    Ok(commits)
+}
+
+/// Pathspec matching the files in the same directory as the file.
+fn sibling_pathspec(file: &str) -> String {
+    match file.rsplit_once('/') {
+        Some((dir, _)) => format!(":(glob){}/*", dir),
+        None => ":(glob)*".to_string(),
+    }
+}
+
+/// Blame has nothing to go on for added and deleted files. For added files
+/// suggest the commits that recently added sibling files or referenced the
+/// file name, for deleted ones the commit that introduced the file and the
+/// ones that last modified it.
+fn get_commits_from_lifecycle(
+    config: &Config,
+    staged_files: &[String],
+    range: &str,
+) -> Result<Vec<String>> {
+    let status = git_changes_status(staged_files, config)?;
+
+    let mut commits = Vec::new();
+    for file in staged_files {
+        // Untracked files of --include-untracked are not part of the diff
+        let status = status
+            .iter()
+            .find(|(_, e)| e == file)
+            .map_or('A', |(status, _)| *status);
+
+        match status {
+            'A' => {
+                commits.extend(git_log_revs(
+                    range,
+                    &["--diff-filter=A"],
+                    &[&sibling_pathspec(file)],
+                    LIFECYCLE_COMMITS,
+                )?);
+                let name = file.rsplit('/').next().unwrap_or(file);
+                let pickaxe = format!("-S{}", name);
+                commits.extend(git_log_revs(range, &[&pickaxe], &[], LIFECYCLE_COMMITS)?);
+            }
+            'D' => {
+                commits.extend(git_log_revs(range, &["--diff-filter=A"], &[file], 1)?);
+                commits.extend(git_log_revs(range, &[], &[file], LIFECYCLE_COMMITS)?);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Format option rendering the label as is, a label without placeholders
+/// would otherwise name a pretty format like oneline.
+fn label_format(label: &str) -> String {
+    format!("--format=tformat:{}", label)
+}
+
+fn render_label(label: &str, color: &str) -> Result<String> {
+    let output = git_log_no_walk(&[color, &label_format(label)], &["HEAD".to_string()])?;
+    Ok(output.trim_end_matches('\n').to_string())
+}
+
+/// Number of characters a rendered label occupies on the terminal, skipping
+/// the ANSI color escape sequences.
+fn console_width(label: &str) -> usize {
+    let mut width = 0;
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|e| e.is_ascii_alphabetic());
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+fn render_placeholders(
+    target: &str,
+    candidate: &Candidate,
+    labels: &HashMap<Source, String>,
+    conflict_label: &str,
+    branch_color: (&str, &str),
+) -> String {
+    let mut target = target.to_string();
+    for (placeholder, sentinel, _) in PLACEHOLDERS {
+        if !target.contains(sentinel) {
+            continue;
+        }
+        let value = match placeholder {
+            "%(smash:source)" => candidate
+                .sources
+                .iter()
+                .filter_map(|source| labels.get(source))
+                .map(|e| e.as_str())
+                .collect::<String>(),
+            "%(smash:blame-lines)" => candidate.blame_lines.to_string(),
+            "%(smash:files)" => candidate.files.to_string(),
+            CONFLICT_PLACEHOLDER => match candidate.conflict {
+                true => conflict_label.to_string(),
+                false => " ".repeat(console_width(conflict_label)),
+            },
+            BRANCHES_PLACEHOLDER => match candidate.branches.is_empty() {
+                true => String::new(),
+                false => format!(
+                    " {}[{}]{}",
+                    branch_color.0,
+                    candidate.branches.join(", "),
+                    branch_color.1
+                ),
+            },
+            _ => String::new(),
+        };
+        target = target.replace(sentinel, &value);
+    }
+    target
+}
+
+/// Pretty prints all candidates with a single git log call, the smash
+/// placeholders are substituted per candidate afterwards.
+pub fn format_candidates(
+    candidates: &[Candidate],
+    config: &Config,
+    color: bool,
+) -> Result<Vec<String>> {
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let color = match color {
+        true => "--color=always",
+        false => "--color=never",
+    };
+
+    let mut labels = HashMap::new();
+    for source in candidates.iter().flat_map(|e| e.sources.iter()) {
+        if !labels.contains_key(source) {
+            labels.insert(*source, render_label(source.label(config), color)?);
+        }
+    }
+
+    let conflict_label = match config.format.contains(CONFLICT_PLACEHOLDER) {
+        true => render_label(&config.conflict_label, color)?,
+        false => String::new(),
+    };
+
+    let branch_color = match config.format.contains(BRANCHES_PLACEHOLDER) {
+        true => (
+            render_label("%C(blue)", color)?,
+            render_label("%C(reset)", color)?,
+        ),
+        false => (String::new(), String::new()),
+    };
+
+    let mut format = config.format.clone();
+    for (placeholder, _, sentinel_format) in PLACEHOLDERS {
+        format = format.replace(placeholder, sentinel_format);
+    }
+    let format = format!("--format=%H {}", format);
+    let commits = candidates
+        .iter()
+        .map(|e| e.commit.clone())
+        .collect::<Vec<_>>();
+    let output = git_log_no_walk(&[color, &format], &commits)?;
+
+    let by_commit = candidates
+        .iter()
+        .map(|e| (e.commit.as_str(), e))
+        .collect::<HashMap<_, _>>();
+
+    let mut targets = Vec::new();
+    for line in output.lines() {
+        let (commit, target) = line
+            .split_once(' ')
+            .context("failed to extract target hash")?;
+        let candidate = by_commit
+            .get(commit)
+            .with_context(|| format!("failed to find candidate {}", commit))?;
+
+        targets.push(render_placeholders(
+            target,
+            candidate,
+            &labels,
+            &conflict_label,
+            (&branch_color.0, &branch_color.1),
+        ));
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(commits: &[&str]) -> Vec<(String, i64)> {
+        commits
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.to_string(), 1000 - i as i64))
+            .collect()
+    }
+
+    #[test]
+    fn test_add_merges_sources() {
+        let mut candidates = Candidates::new();
+        candidates.add("a", Source::Blame).blame_lines += 1;
+        candidates.add("b", Source::Files);
+        candidates.add("a", Source::Blame).blame_lines += 1;
+        candidates.add("a", Source::Files).files = 2;
+
+        let candidates = candidates.into_vec();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].sources, vec![Source::Blame, Source::Files]);
+        assert_eq!(candidates[0].blame_lines, 2);
+        assert_eq!(candidates[0].files, 2);
+    }
+
+    #[test]
+    fn test_auto_target() {
+        let mut candidates = Candidates::new();
+        candidates.add("a", Source::Blame).blame_lines += 4;
+        candidates.add("b", Source::Blame).blame_lines += 1;
+        candidates.add("c", Source::Recent);
+        let candidates = candidates.into_vec();
+
+        assert_eq!(
+            auto_target(&candidates, 80).map(|e| e.commit.as_str()),
+            Some("a")
+        );
+        assert!(auto_target(&candidates, 81).is_none());
+        assert!(auto_target(&candidates[2..], 0).is_none());
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        let mut candidates = Candidates::new();
+        candidates.add("a", Source::Blame).blame_lines += 3;
+        candidates.add("a", Source::Files).files = 2;
+        let candidates = candidates.into_vec();
+
+        let labels = HashMap::from([
+            (Source::Blame, "B".to_string()),
+            (Source::Files, "F".to_string()),
+        ]);
+        let target =
+            render_placeholders("a [\x01] \x02 \x03", &candidates[0], &labels, "!", ("", ""));
+        assert_eq!(target, "a [BF] 3 2");
+    }
+
+    #[test]
+    fn test_render_conflict_marker() {
+        let mut candidates = Candidates::new();
+        candidates.add("a", Source::Blame);
+        candidates.add("b", Source::Blame).conflict = true;
+        let candidates = candidates.into_vec();
+
+        let label = "\x1b[31m!\x1b[m";
+        let labels = HashMap::new();
+        assert_eq!(
+            render_placeholders("\x04 a", &candidates[0], &labels, label, ("", "")),
+            "  a"
+        );
+        assert_eq!(
+            render_placeholders("\x04 b", &candidates[1], &labels, label, ("", "")),
+            format!("{} b", label)
+        );
+    }
+
+    #[test]
+    fn test_render_branches() {
+        let mut candidates = Candidates::new();
+        candidates.add("a", Source::Blame);
+        candidates.add("b", Source::Blame).branches = vec!["x".into(), "y".into()];
+        let candidates = candidates.into_vec();
+
+        let labels = HashMap::new();
+        let render = |candidate| render_placeholders("c\x05", candidate, &labels, "", ("<", ">"));
+        assert_eq!(render(&candidates[0]), "c");
+        assert_eq!(render(&candidates[1]), "c <[x, y]>");
+    }
+
+    #[test]
+    fn test_label_format() {
+        assert_eq!(label_format("J"), "--format=tformat:J");
+        assert_eq!(label_format("oneline"), "--format=tformat:oneline");
+        assert_eq!(
+            label_format("%C(red)J%C(reset)"),
+            "--format=tformat:%C(red)J%C(reset)"
+        );
+    }
+
+    #[test]
+    fn test_rank_prefers_blame_over_recency() {
+        let mut candidates = Candidates::new();
+        candidates.add("recent", Source::Recent);
+        candidates.add("blamed", Source::Blame).blame_lines += 3;
+        candidates.add("other", Source::Blame).blame_lines += 1;
+        candidates.rank(1, &times(&["recent", "other", "blamed"]));
+
+        let order = candidates
+            .into_vec()
+            .into_iter()
+            .map(|e| e.commit)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec!["blamed", "other", "recent"]);
+    }
+
+    #[test]
+    fn test_rank_prefers_agreeing_sources() {
+        let mut candidates = Candidates::new();
+        candidates.add("files", Source::Files).files = 1;
+        candidates.add("both", Source::Files).files = 1;
+        candidates.add("both", Source::Recent);
+        candidates.rank(2, &times(&["files", "both"]));
+
+        let candidates = candidates.into_vec();
+        assert_eq!(candidates[0].commit, "both");
+        assert!(candidates[0].score > candidates[1].score);
+    }
+
+    #[test]
+    fn test_sibling_pathspec() {
+        assert_eq!(sibling_pathspec("src/git.rs"), ":(glob)src/*");
+        assert_eq!(sibling_pathspec("a/b/c.txt"), ":(glob)a/b/*");
+        assert_eq!(sibling_pathspec("README.md"), ":(glob)*");
+    }
+
+    #[test]
+    fn test_candidates_redirect_fixups() {
+        let mut candidates = Candidates::new();
+        candidates.add("fixup", Source::Blame).blame_lines += 2;
+        candidates.add("target", Source::Files).files = 1;
+        candidates.add("orphan", Source::Recent);
+        candidates.add("target", Source::Blame).blame_lines += 1;
+        let targets = vec![
+            ("fixup".to_string(), Some("target".to_string())),
+            ("orphan".to_string(), None),
+        ];
+        let candidates = candidates.redirect(&targets).into_vec();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].commit, "target");
+        assert_eq!(candidates[0].sources, vec![Source::Blame, Source::Files]);
+        assert_eq!((candidates[0].blame_lines, candidates[0].files), (3, 1));
+    }
+
+    #[test]
+    fn test_removed_identifiers() -> Result<()> {
+        let diff = "diff --git src/a.rs src/a.rs
+--- src/a.rs
++++ src/a.rs
+@@ -1,2 +1,2 @@ fn main() {
+-    let count = old_name(value);
+-    old_name(count);
++    let count = new_name(value);
++    new_name(count);
+";
+        let identifiers = removed_identifiers(&parse_diff(diff)?)?;
+        assert_eq!(identifiers, vec!["old_name"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ticket_keys() -> Result<()> {
+        let pattern = crate::config::DEFAULT_TICKET_PATTERN;
+        assert_eq!(
+            ticket_keys(pattern, "feature/ABC-123-fix-ABC-123-and-XY2-7")?,
+            vec!["ABC-123", "XY2-7"]
+        );
+        assert!(ticket_keys(pattern, "main")?.is_empty());
+        assert!(ticket_keys("(", "main").is_err());
+        let re_key = ticket_regex("A.B-1")?;
+        assert!(re_key.is_match("A.B-1: fix"));
+        assert!(re_key.is_match("Refs: XY-2, A.B-1"));
+        assert!(!re_key.is_match("AxB-1"));
+        assert!(!re_key.is_match("A.B-12"));
+        Ok(())
+    }
+}
@@ -1,16 +1,22 @@
 use crate::args::Args;
 use crate::errors::*;
-use crate::git::{git_check_version, git_version, GitConfigBuilder};
+use crate::git::{git_check_version, git_config_regexp, git_version, GitConfigBuilder};
 
 use std::str::FromStr;
 
 use strum_macros::{Display, EnumString};
 
 pub const DEFAULT_LIST_FORMAT: &str =
-    "%C(yellow)%h%C(reset) [%(smash:source)] %s %C(cyan)<%an>%C(reset) %C(green)(%cr)%C(reset)%C(auto)%d%C(reset)";
+    "%C(yellow)%h%C(reset) [%(smash:source)] %s %C(cyan)<%an>%C(reset) %C(green)(%cr)%C(reset)%C(auto)%d%C(reset)%(smash:branches)";
 pub const DEFAULT_FORMAT_SOURCE_FILES: &str = "%C(green)F%C(reset)";
 pub const DEFAULT_FORMAT_SOURCE_BLAME: &str = "%C(red)B%C(reset)";
 pub const DEFAULT_FORMAT_SOURCE_RECENT: &str = "%C(magenta)R%C(reset)️️";
+pub const DEFAULT_FORMAT_SOURCE_LIFECYCLE: &str = "%C(blue)N%C(reset)";
+pub const DEFAULT_FORMAT_SOURCE_FUNCTIONS: &str = "%C(cyan)L%C(reset)";
+pub const DEFAULT_FORMAT_SOURCE_PICKAXE: &str = "%C(yellow)P%C(reset)";
+pub const DEFAULT_FORMAT_SOURCE_TICKETS: &str = "%C(bold blue)T%C(reset)";
+pub const DEFAULT_TICKET_PATTERN: &str = "[A-Z][A-Z0-9]+-[0-9]+";
+pub const DEFAULT_FORMAT_CONFLICT: &str = "%C(red)!%C(reset)";
 
 #[derive(Debug, PartialEq, Eq, Display, EnumString)]
 #[strum(serialize_all = "snake_case")]
@@ -20,12 +26,28 @@ pub enum DisplayMode {
     Select,
 }
 
+#[derive(Debug, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum RebaseBackend {
+    Classic,
+    Memory,
+}
+
+#[derive(Debug, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ProtectedAction {
+    Ask,
+    Refuse,
+    Allow,
+}
+
 pub enum CommitRange {
     Local,
     All,
     Range(String),
 }
 
+#[derive(Clone, Copy)]
 pub enum FixupMode {
     Fixup,
     Amend,
@@ -33,13 +55,57 @@ pub enum FixupMode {
 }
 
 impl FixupMode {
-    pub fn to_cli_option(&self, target: &str) -> String {
+    pub fn to_cli_option(self, target: &str) -> String {
         match self {
             Self::Fixup => format!("--fixup={}", target),
             Self::Amend => format!("--fixup=amend:{}", target),
             Self::Reword => format!("--fixup=reword:{}", target),
         }
     }
+
+    pub fn to_subject(self, target_subject: &str) -> String {
+        match self {
+            Self::Fixup => format!("fixup! {}", target_subject),
+            Self::Amend | Self::Reword => format!("amend! {}", target_subject),
+        }
+    }
+}
+
+/// User defined candidate source configured by smash.source.<name>.command
+/// and optionally smash.source.<name>.label.
+pub struct ExternalSource {
+    pub name: String,
+    pub command: String,
+    pub label: String,
+}
+
+impl ExternalSource {
+    fn load_all() -> Result<Vec<Self>> {
+        let entries = git_config_regexp(r"^smash\.source\..*\.(command|label)$")?;
+        let mut sources: Vec<Self> = Vec::new();
+        for (key, command) in &entries {
+            let name = match key
+                .strip_prefix("smash.source.")
+                .and_then(|e| e.strip_suffix(".command"))
+            {
+                Some(name) => name,
+                None => continue,
+            };
+            let label = entries
+                .iter()
+                .rev()
+                .find(|(e, _)| *e == format!("smash.source.{}.label", name))
+                .map_or_else(|| name.to_string(), |(_, label)| label.to_string());
+            // A later definition of the same source overrides the earlier one
+            sources.retain(|e| e.name != name);
+            sources.push(Self {
+                name: name.to_string(),
+                command: command.to_string(),
+                label,
+            });
+        }
+        Ok(sources)
+    }
 }
 
 pub struct Config {
@@ -49,19 +115,50 @@ pub struct Config {
     pub max_count: u32,
     pub auto_rebase: bool,
     pub interactive: bool,
+    pub rebase_backend: RebaseBackend,
+    pub squash_pending: bool,
+    pub update_refs: bool,
+    pub update_refs_option: Option<String>,
     pub blame: bool,
     pub files: bool,
+    pub lifecycle: bool,
+    pub functions: bool,
+    pub pickaxe: bool,
+    pub tickets: bool,
+    pub ticket_pattern: String,
+    pub blame_move: bool,
+    pub blame_copy: bool,
+    pub follow_renames: bool,
     pub recent: u32,
+    pub rank: bool,
+    pub auto: bool,
+    pub auto_threshold: u32,
     pub commit: Option<String>,
+    pub dry_run: bool,
+    pub patch: bool,
+    pub all_changes: bool,
+    pub include_untracked: bool,
+    pub pathspecs: Vec<String>,
+    pub require_clean: bool,
+    pub protected_refs: Option<String>,
+    pub protected_action: ProtectedAction,
     pub source_label_files: String,
     pub source_label_blame: String,
     pub source_label_recent: String,
+    pub source_label_lifecycle: String,
+    pub source_label_functions: String,
+    pub source_label_pickaxe: String,
+    pub source_label_tickets: String,
+    pub external_sources: Vec<ExternalSource>,
+    pub conflict_label: String,
     pub fixup_mode: FixupMode,
     pub gpg_sign_option: Option<String>,
     pub verify_option: Option<String>,
     pub pager: Option<String>,
     pub ext_diff: Option<String>,
     pub preview_window: String,
+    pub menu: Option<String>,
+    pub menu_args: Option<String>,
 }
 
 impl Config {
@@ -131,6 +228,49 @@ impl Config {
                     .get_as_bool()?
                     .unwrap_or(false)
             },
+            rebase_backend: match GitConfigBuilder::new("smash.rebaseBackend").get()? {
+                Some(backend) => {
+                    let backend = RebaseBackend::from_str(&backend).with_context(|| {
+                        format!("failed to parse smash.rebaseBackend '{}'", backend)
+                    })?;
+                    if backend == RebaseBackend::Memory {
+                        git_check_version(&git_version, ">=2.38", "smash.rebaseBackend=memory")?;
+                    }
+                    backend
+                }
+                None => RebaseBackend::Classic,
+            },
+            update_refs: if args.update_refs {
+                true
+            } else if args.no_update_refs {
+                false
+            } else {
+                GitConfigBuilder::new("rebase.updateRefs")
+                    .with_type("bool")
+                    .with_default("false")
+                    .get_as_bool()?
+                    .unwrap_or(false)
+            },
+            update_refs_option: if args.update_refs {
+                git_check_version(&git_version, ">=2.38", "--update-refs")?;
+                Some("--update-refs".into())
+            } else if args.no_update_refs {
+                git_check_version(&git_version, ">=2.38", "--no-update-refs")?;
+                Some("--no-update-refs".into())
+            } else {
+                None
+            },
+            squash_pending: if args.squash_pending {
+                true
+            } else if args.no_squash_pending {
+                false
+            } else {
+                GitConfigBuilder::new("smash.squashPending")
+                    .with_type("bool")
+                    .with_default("true")
+                    .get_as_bool()?
+                    .unwrap_or(true)
+            },
             blame: if args.blame {
                 true
             } else if args.no_blame {
@@ -153,6 +293,69 @@ impl Config {
                     .get_as_bool()?
                     .unwrap_or(true)
             },
+            lifecycle: if args.lifecycle {
+                true
+            } else if args.no_lifecycle {
+                false
+            } else {
+                GitConfigBuilder::new("smash.lifecycle")
+                    .with_type("bool")
+                    .with_default("true")
+                    .get_as_bool()?
+                    .unwrap_or(true)
+            },
+            functions: if args.functions {
+                true
+            } else if args.no_functions {
+                false
+            } else {
+                GitConfigBuilder::new("smash.functions")
+                    .with_type("bool")
+                    .with_default("false")
+                    .get_as_bool()?
+                    .unwrap_or(false)
+            },
+            pickaxe: if args.pickaxe {
+                true
+            } else if args.no_pickaxe {
+                false
+            } else {
+                GitConfigBuilder::new("smash.pickaxe")
+                    .with_type("bool")
+                    .with_default("false")
+                    .get_as_bool()?
+                    .unwrap_or(false)
+            },
+            tickets: if args.tickets {
+                true
+            } else if args.no_tickets {
+                false
+            } else {
+                GitConfigBuilder::new("smash.tickets")
+                    .with_type("bool")
+                    .with_default("false")
+                    .get_as_bool()?
+                    .unwrap_or(false)
+            },
+            ticket_pattern: GitConfigBuilder::new("smash.ticketPattern")
+                .with_default(DEFAULT_TICKET_PATTERN)
+                .get()?
+                .unwrap_or_else(|| DEFAULT_TICKET_PATTERN.into()),
+            blame_move: GitConfigBuilder::new("smash.blameMove")
+                .with_type("bool")
+                .with_default("true")
+                .get_as_bool()?
+                .unwrap_or(true),
+            blame_copy: GitConfigBuilder::new("smash.blameCopy")
+                .with_type("bool")
+                .with_default("false")
+                .get_as_bool()?
+                .unwrap_or(false),
+            follow_renames: GitConfigBuilder::new("smash.followRenames")
+                .with_type("bool")
+                .with_default("true")
+                .get_as_bool()?
+                .unwrap_or(true),
             recent: if let Some(recent) = args.recent {
                 recent
             } else {
@@ -162,6 +365,37 @@ impl Config {
                     .get_as_int()?
                     .unwrap_or(0)
             },
+            rank: if args.rank {
+                true
+            } else if args.no_rank {
+                false
+            } else {
+                GitConfigBuilder::new("smash.rank")
+                    .with_type("bool")
+                    .with_default("true")
+                    .get_as_bool()?
+                    .unwrap_or(true)
+            },
+            auto: if args.auto {
+                true
+            } else if args.no_auto {
+                false
+            } else {
+                GitConfigBuilder::new("smash.auto")
+                    .with_type("bool")
+                    .with_default("false")
+                    .get_as_bool()?
+                    .unwrap_or(false)
+            },
+            auto_threshold: if let Some(auto_threshold) = args.auto_threshold {
+                auto_threshold
+            } else {
+                GitConfigBuilder::new("smash.autoThreshold")
+                    .with_type("int")
+                    .with_default("80")
+                    .get_as_int()?
+                    .unwrap_or(80)
+            },
             source_label_files: GitConfigBuilder::new("smash.filesSourceFormat")
                 .with_default(DEFAULT_FORMAT_SOURCE_FILES)
                 .get()?
@@ -174,7 +408,55 @@ impl Config {
                 .with_default(DEFAULT_FORMAT_SOURCE_RECENT)
                 .get()?
                 .unwrap_or_else(|| DEFAULT_FORMAT_SOURCE_RECENT.into()),
+            source_label_lifecycle: GitConfigBuilder::new("smash.lifecycleSourceFormat")
+                .with_default(DEFAULT_FORMAT_SOURCE_LIFECYCLE)
+                .get()?
+                .unwrap_or_else(|| DEFAULT_FORMAT_SOURCE_LIFECYCLE.into()),
+            source_label_functions: GitConfigBuilder::new("smash.functionsSourceFormat")
+                .with_default(DEFAULT_FORMAT_SOURCE_FUNCTIONS)
+                .get()?
+                .unwrap_or_else(|| DEFAULT_FORMAT_SOURCE_FUNCTIONS.into()),
+            source_label_pickaxe: GitConfigBuilder::new("smash.pickaxeSourceFormat")
+                .with_default(DEFAULT_FORMAT_SOURCE_PICKAXE)
+                .get()?
+                .unwrap_or_else(|| DEFAULT_FORMAT_SOURCE_PICKAXE.into()),
+            source_label_tickets: GitConfigBuilder::new("smash.ticketsSourceFormat")
+                .with_default(DEFAULT_FORMAT_SOURCE_TICKETS)
+                .get()?
+                .unwrap_or_else(|| DEFAULT_FORMAT_SOURCE_TICKETS.into()),
+            external_sources: ExternalSource::load_all()
+                .context("failed to load smash.source configuration")?,
+            conflict_label: GitConfigBuilder::new("smash.conflictFormat")
+                .with_default(DEFAULT_FORMAT_CONFLICT)
+                .get()?
+                .unwrap_or_else(|| DEFAULT_FORMAT_CONFLICT.into()),
             commit: args.commit.clone(),
+            dry_run: args.dry_run,
+            patch: args.patch,
+            all_changes: args.all_changes,
+            include_untracked: args.include_untracked,
+            pathspecs: args.pathspecs.clone(),
+            protected_refs: GitConfigBuilder::new("smash.protectedRefs").get()?,
+            protected_action: if args.allow_protected {
+                ProtectedAction::Allow
+            } else if let Some(action) = GitConfigBuilder::new("smash.protectedAction").get()? {
+                ProtectedAction::from_str(&action).with_context(|| {
+                    format!("failed to parse smash.protectedAction '{}'", action)
+                })?
+            } else {
+                ProtectedAction::Ask
+            },
+            require_clean: if args.require_clean {
+                true
+            } else if args.no_require_clean {
+                false
+            } else {
+                GitConfigBuilder::new("smash.requireClean")
+                    .with_type("bool")
+                    .with_default("false")
+                    .get_as_bool()?
+                    .unwrap_or(false)
+            },
             fixup_mode: if args.amend {
                 git_check_version(&git_version, ">=2.33", "--amend")?;
                 FixupMode::Amend
@@ -224,8 +506,26 @@ impl Config {
                     .get()?
                     .unwrap()
             },
+            menu: if let Some(menu) = &args.menu {
+                Some(menu.to_string())
+            } else {
+                GitConfigBuilder::new("smash.menu").get()?
+            },
+            menu_args: GitConfigBuilder::new("smash.menuArgs").get()?,
         };
 
         Ok(config)
     }
+
+    /// Options passed to git blame to detect moved and copied lines.
+    pub fn blame_options(&self) -> Vec<&'static str> {
+        let mut options = Vec::new();
+        if self.blame_move {
+            options.push("-M");
+        }
+        if self.blame_copy {
+            options.push("-C");
+        }
+        options
+    }
 }
@@ -0,0 +1,305 @@
+use crate::errors::*;
+
+use regex::Regex;
+use std::collections::HashSet;
+
+pub struct Hunk {
+    pub old_start: u32,
+    pub old_len: u32,
+    pub new_start: u32,
+    pub new_len: u32,
+    pub context: Option<String>,
+    pub lines: Vec<String>,
+}
+
+impl Hunk {
+    pub const fn delta(&self) -> i64 {
+        self.new_len as i64 - self.old_len as i64
+    }
+
+    /// Lines of the pre-image that are touched by this hunk. Pure additions
+    /// have no pre-image lines, so the lines surrounding the insertion point
+    /// are used instead.
+    pub fn blame_range(&self, line_count: u32) -> Option<(u32, u32)> {
+        let (start, end) = if self.old_len > 0 {
+            (self.old_start, self.old_start + self.old_len - 1)
+        } else {
+            (self.old_start.max(1), self.old_start + 1)
+        };
+        let end = end.min(line_count);
+        if line_count == 0 || start > end {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    /// Name of the function the hunk is in, taken from the function context
+    /// git prints after the hunk header.
+    pub fn function_name(&self) -> Option<&str> {
+        let context = self.context.as_deref()?;
+        // Skip generic parameters between the name and the parameter list
+        let end = context.find('(')?;
+        let name = context[..end]
+            .split('<')
+            .next()?
+            .trim_end()
+            .rsplit(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()?;
+        (!name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit())).then_some(name)
+    }
+
+    pub fn header(&self, old_start: u32, new_start: u32) -> String {
+        format!(
+            "@@ -{},{} +{},{} @@",
+            old_start, self.old_len, new_start, self.new_len
+        )
+    }
+}
+
+pub struct FileDiff {
+    pub old_file: Option<String>,
+    pub new_file: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FileDiff {
+    /// A plain content modification of a file that exists on both sides.
+    pub fn is_modification(&self) -> bool {
+        matches!((&self.old_file, &self.new_file), (Some(old), Some(new)) if old == new)
+    }
+}
+
+fn parse_file_name(name: &str) -> Option<String> {
+    let name = name.trim_end_matches('\t');
+    if name == "/dev/null" {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+/// Parses the output of `git diff --no-prefix` into files and hunks.
+pub fn parse_diff(diff: &str) -> Result<Vec<FileDiff>> {
+    let re_chunk = Regex::new(r"^@@ -([0-9]+)(,([0-9]+))? \+([0-9]+)(,([0-9]+))? @@ ?(.*)$")?;
+
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut in_header = false;
+
+    for line in diff.lines() {
+        if line.starts_with("diff ") {
+            files.push(FileDiff {
+                old_file: None,
+                new_file: None,
+                hunks: Vec::new(),
+            });
+            in_header = true;
+            continue;
+        }
+        let file = match files.last_mut() {
+            Some(file) => file,
+            None => continue,
+        };
+        if in_header {
+            if let Some(name) = line.strip_prefix("--- ") {
+                file.old_file = parse_file_name(name);
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("+++ ") {
+                file.new_file = parse_file_name(name);
+                continue;
+            }
+        }
+        if let Some(chunk) = re_chunk.captures(line) {
+            in_header = false;
+            file.hunks.push(Hunk {
+                old_start: chunk_number(&chunk, 1)?.unwrap_or(0),
+                old_len: chunk_number(&chunk, 3)?.unwrap_or(1),
+                new_start: chunk_number(&chunk, 4)?.unwrap_or(0),
+                new_len: chunk_number(&chunk, 6)?.unwrap_or(1),
+                context: chunk
+                    .get(7)
+                    .map(|e| e.as_str().to_string())
+                    .filter(|e| !e.is_empty()),
+                lines: Vec::new(),
+            });
+            continue;
+        }
+        if in_header {
+            continue;
+        }
+        if let Some(hunk) = file.hunks.last_mut() {
+            hunk.lines.push(line.to_string());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Maps a line of the post-image of the hunks back to the pre-image, lines
+/// the hunks added have no pre-image line.
+pub fn map_to_old_line(hunks: &[Hunk], line: u32) -> Option<u32> {
+    let mut delta = 0;
+    for hunk in hunks {
+        let after = match hunk.new_len {
+            0 => hunk.new_start + 1,
+            len => hunk.new_start + len,
+        };
+        if line >= after {
+            delta += hunk.delta();
+        } else if line >= hunk.new_start && hunk.new_len > 0 {
+            return None;
+        } else {
+            break;
+        }
+    }
+    u32::try_from(i64::from(line) - delta).ok()
+}
+
+/// Builds a zero context patch of the selected hunks that applies on top of
+/// the pre-image after all previously committed hunks have been applied.
+pub fn build_patch(
+    files: &[FileDiff],
+    hunks: &[(usize, usize)],
+    committed: &HashSet<(usize, usize)>,
+) -> String {
+    let mut patch = String::new();
+
+    for (file_index, file_diff) in files.iter().enumerate() {
+        if !hunks.iter().any(|(index, _)| *index == file_index) {
+            continue;
+        }
+        let file = file_diff.old_file.as_deref().unwrap_or_default();
+        patch.push_str(&format!(
+            "diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n",
+            file
+        ));
+
+        let mut delta_all = 0;
+        let mut delta_committed = 0;
+        let mut delta_patch = 0;
+        for (hunk_index, hunk) in file_diff.hunks.iter().enumerate() {
+            let key = (file_index, hunk_index);
+            if hunks.contains(&key) {
+                let old_start = hunk.old_start as i64 + delta_committed;
+                let new_start = hunk.new_start as i64 - delta_all + delta_committed + delta_patch;
+                patch.push_str(&hunk.header(old_start as u32, new_start as u32));
+                patch.push('\n');
+                for line in &hunk.lines {
+                    patch.push_str(line);
+                    patch.push('\n');
+                }
+                delta_patch += hunk.delta();
+            } else if committed.contains(&key) {
+                delta_committed += hunk.delta();
+            }
+            delta_all += hunk.delta();
+        }
+    }
+
+    patch
+}
+
+fn chunk_number(chunk: &regex::Captures, index: usize) -> Result<Option<u32>> {
+    chunk
+        .get(index)
+        .map(|m| {
+            m.as_str()
+                .parse::<u32>()
+                .with_context(|| format!("failed to parse hunk header number '{}'", m.as_str()))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "diff --git src/main.rs src/main.rs
+index 1111111..2222222 100644
+--- src/main.rs
++++ src/main.rs
+@@ -3 +3 @@ fn main() {
+-    old();
++    new();
+@@ -10,0 +11,2 @@ fn other() {
++    one();
++    two();
+diff --git src/new.rs src/new.rs
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ src/new.rs
+@@ -0,0 +1 @@
++fn new() {}
+";
+
+    #[test]
+    fn test_parse_diff_files() -> Result<()> {
+        let files = parse_diff(DIFF)?;
+        assert_eq!(files.len(), 2);
+        assert!(files[0].is_modification());
+        assert_eq!(files[1].old_file, None);
+        assert_eq!(files[1].new_file.as_deref(), Some("src/new.rs"));
+        assert!(!files[1].is_modification());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_diff_hunks() -> Result<()> {
+        let files = parse_diff(DIFF)?;
+        let hunks = &files[0].hunks;
+        assert_eq!(hunks.len(), 2);
+        assert_eq!((hunks[0].old_start, hunks[0].old_len), (3, 1));
+        assert_eq!((hunks[0].new_start, hunks[0].new_len), (3, 1));
+        assert_eq!(hunks[0].lines, vec!["-    old();", "+    new();"]);
+        assert_eq!((hunks[1].old_start, hunks[1].old_len), (10, 0));
+        assert_eq!(hunks[1].delta(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_to_old_line() -> Result<()> {
+        let files = parse_diff(DIFF)?;
+        let hunks = &files[0].hunks;
+        assert_eq!(map_to_old_line(hunks, 2), Some(2));
+        assert_eq!(map_to_old_line(hunks, 3), None);
+        assert_eq!(map_to_old_line(hunks, 10), Some(10));
+        assert_eq!(map_to_old_line(hunks, 11), None);
+        assert_eq!(map_to_old_line(hunks, 12), None);
+        assert_eq!(map_to_old_line(hunks, 13), Some(11));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hunk_blame_range() -> Result<()> {
+        let files = parse_diff(DIFF)?;
+        let hunks = &files[0].hunks;
+        assert_eq!(hunks[0].blame_range(20), Some((3, 3)));
+        assert_eq!(hunks[1].blame_range(20), Some((10, 11)));
+        assert_eq!(hunks[1].blame_range(10), Some((10, 10)));
+        assert_eq!(hunks[1].blame_range(0), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hunk_function_name() -> Result<()> {
+        let files = parse_diff(DIFF)?;
+        assert_eq!(files[0].hunks[0].function_name(), Some("main"));
+        assert_eq!(files[0].hunks[1].function_name(), Some("other"));
+        assert_eq!(files[1].hunks[0].function_name(), None);
+
+        let mut hunk = parse_diff(DIFF)?.remove(0).hunks.remove(0);
+        for (context, name) in [
+            ("pub fn git_blame<'a>(", Some("git_blame")),
+            (
+                "static int run_command (struct cmd *c)",
+                Some("run_command"),
+            ),
+            ("    def parse(self, line):", Some("parse")),
+            ("impl Hunk {", None),
+        ] {
+            hunk.context = Some(context.to_string());
+            assert_eq!(hunk.function_name(), name);
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,18 @@
 use crate::errors::*;
 
 use crate::config::{CommitRange, Config, FixupMode};
+use crate::todo::TodoCommit;
 use regex::Regex;
 use semver::{Version, VersionReq};
-use std::path::PathBuf;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::slice;
+use std::thread;
 
 pub struct GitConfigBuilder {
     key: &'static str,
@@ -88,31 +96,77 @@ impl GitConfigBuilder {
     }
 }
 
+/// Returns the keys and values of all config entries matching the regex.
+pub fn git_config_regexp(pattern: &str) -> Result<Vec<(String, String)>> {
+    let args = vec!["config", "--null", "--get-regexp", pattern];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        match output.status.code() {
+            Some(1) => return Ok(Vec::new()),
+            _ => bail!("{}", String::from_utf8_lossy(&output.stderr).trim()),
+        }
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|e| !e.is_empty())
+        .map(|entry| {
+            let (key, value) = entry.split_once('\n').unwrap_or((entry, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect())
+}
+
+/// Rebases onto the parent of the given commit, with a todo file the todo
+/// list is taken from it instead of git's autosquash.
 pub fn git_rebase(
     rev: &str,
+    todo: Option<&Path>,
     interactive: bool,
     gpg_sign: &Option<String>,
     verify: &Option<String>,
+    update_refs: &Option<String>,
 ) -> Result<()> {
-    let root = git_rev_root().context("failed to get git rev root")?;
-    let rev = match root.starts_with(rev) {
-        true => "--root".to_string(),
-        false => format!("{}^", rev),
-    };
+    let rev = git_rebase_base(rev)?.unwrap_or_else(|| "--root".to_string());
 
-    let mut args = vec!["rebase", "--interactive", "--autosquash", "--autostash"];
+    let mut args = vec!["rebase", "--interactive", "--autostash"];
+    args.push(match todo {
+        Some(_) => "--no-autosquash",
+        None => "--autosquash",
+    });
     if let Some(verify) = verify {
         args.push(verify);
     }
     if let Some(gpg_sign) = gpg_sign {
         args.push(gpg_sign);
     }
+    if let Some(update_refs) = update_refs {
+        args.push(update_refs);
+    }
     args.push(&rev);
     let mut cmd = Command::new("git");
     if !interactive {
         cmd.env("GIT_EDITOR", "true");
-        cmd.env("GIT_SEQUENCE_EDITOR", "true");
     }
+    match (todo, interactive) {
+        (Some(todo), true) => cmd.env(
+            "GIT_SEQUENCE_EDITOR",
+            format!(
+                "f() {{ cp {} \"$1\" && {} \"$1\"; }}; f",
+                shell_quote(&todo.to_string_lossy()),
+                git_sequence_editor()?
+            ),
+        ),
+        (Some(todo), false) => cmd.env(
+            "GIT_SEQUENCE_EDITOR",
+            format!("cp {}", shell_quote(&todo.to_string_lossy())),
+        ),
+        (None, true) => &mut cmd,
+        (None, false) => cmd.env("GIT_SEQUENCE_EDITOR", "true"),
+    };
     let cmd = cmd.args(&args).spawn()?;
     let output = cmd.wait_with_output()?;
 
@@ -123,6 +177,105 @@ pub fn git_rebase(
     Ok(())
 }
 
+/// Drives a stopped rebase with --continue, --abort or --skip.
+pub fn git_rebase_resume(action: &str) -> Result<()> {
+    let args = vec!["rebase", action];
+    let cmd = Command::new("git").args(&args).spawn()?;
+    let output = cmd.wait_with_output()?;
+
+    if !output.status.success() {
+        bail!("git rebase {} failed", action);
+    }
+
+    Ok(())
+}
+
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Returns the editor git would open for the rebase todo list.
+pub fn git_sequence_editor() -> Result<String> {
+    if let Ok(editor) = env::var("GIT_SEQUENCE_EDITOR") {
+        return Ok(editor);
+    }
+    if let Some(editor) = GitConfigBuilder::new("sequence.editor").get()? {
+        return Ok(editor);
+    }
+    let args = vec!["var", "GIT_EDITOR"];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
+
+/// Returns the upstream a rebase of the given commit starts from or None if
+/// the commit is the root commit.
+pub fn git_rebase_base(rev: &str) -> Result<Option<String>> {
+    let root = git_rev_root().context("failed to get git rev root")?;
+    match root.starts_with(rev) {
+        true => Ok(None),
+        false => Ok(Some(format!("{}^", rev))),
+    }
+}
+
+/// Lists the commits a rebase onto the given base would pick, oldest first.
+pub fn git_rebase_commits(base: &Option<String>) -> Result<Vec<TodoCommit>> {
+    let range = base
+        .as_ref()
+        .map_or_else(|| "HEAD".to_string(), |base| format!("{}..HEAD", base));
+    git_range_commits(&range)
+}
+
+/// Lists the non-merge commits of a rev range, oldest first.
+pub fn git_range_commits(range: &str) -> Result<Vec<TodoCommit>> {
+    let args = vec![
+        "--no-pager",
+        "log",
+        "--reverse",
+        "--no-merges",
+        "--format=%H %s",
+        range,
+    ];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            let (commit, subject) = line.split_once(' ').unwrap_or((line, ""));
+            Ok(TodoCommit::new(commit, subject))
+        })
+        .collect()
+}
+
+pub fn git_subject(rev: &str) -> Result<String> {
+    let args = vec!["--no-pager", "log", "-1", "--format=%s", rev];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
+
 pub fn git_rev_root() -> Result<String> {
     let args = vec!["rev-list", "--max-parents=0", "--no-abbrev-commit", "HEAD"];
     let output = Command::new("git")
@@ -195,6 +348,80 @@ pub fn git_rev_list(rev: &str, max_count: u32) -> Result<Vec<String>> {
         .collect())
 }
 
+/// Returns the commits of the range git log finds with the given options,
+/// skipping pending fixup commits, a max count of 0 lists all of them.
+pub fn git_log_revs(
+    range: &str,
+    options: &[&str],
+    pathspecs: &[&str],
+    max_count: u32,
+) -> Result<Vec<String>> {
+    let max_count_arg = format!("-{}", max_count);
+    let mut args = vec![
+        "--no-pager",
+        "log",
+        "--color=never",
+        "--no-ext-diff",
+        "--invert-grep",
+        "--extended-regexp",
+        "--grep",
+        "^(fixup|squash)! .*$",
+        "--format=%H",
+    ];
+    if max_count > 0 {
+        args.push(&max_count_arg);
+    }
+    args.extend(options);
+    args.push(range);
+    args.push("--");
+    args.extend(pathspecs);
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|e| e.to_owned())
+        .collect())
+}
+
+/// Lists the commits of the range with their subject and unfolded trailer
+/// lines, newest first.
+pub fn git_subjects_trailers(range: &str) -> Result<Vec<(String, String, String)>> {
+    let args = vec![
+        "--no-pager",
+        "log",
+        "-z",
+        "--color=never",
+        "--format=%H%n%s%n%(trailers:only,unfold)",
+        range,
+        "--",
+    ];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|e| !e.is_empty())
+        .map(|e| {
+            let mut parts = e.splitn(3, '\n');
+            let commit = parts.next().unwrap_or_default().to_owned();
+            let subject = parts.next().unwrap_or_default().to_owned();
+            let trailers = parts.next().unwrap_or_default().to_owned();
+            (commit, subject, trailers)
+        })
+        .collect())
+}
+
 pub fn git_toplevel() -> Result<PathBuf> {
     git_rev_parse("--show-toplevel").map(PathBuf::from)
 }
@@ -209,6 +436,90 @@ pub fn is_valid_git_rev(rev: &str) -> Result<bool> {
     Ok(cmd.wait()?.success())
 }
 
+pub fn git_is_ancestor(ancestor: &str, rev: &str) -> Result<bool> {
+    let args = vec!["merge-base", "--is-ancestor", ancestor, rev];
+    let output = Command::new("git")
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end()),
+    }
+}
+
+/// Returns the commits of the list that are part of the range, testing their
+/// ancestry against the ends of the range instead of listing it.
+pub fn git_range_contains(range: &str, commits: &[String]) -> Result<HashSet<String>> {
+    let bounds = git_rev_parse(range)?;
+    let (exclude, include): (Vec<&str>, Vec<&str>) =
+        bounds.lines().partition(|e| e.starts_with('^'));
+    let exclude = exclude
+        .iter()
+        .map(|e| e.trim_start_matches('^'))
+        .collect::<Vec<_>>();
+
+    let mut contained = HashSet::new();
+    let mut checked = HashSet::new();
+    for commit in commits {
+        if !checked.insert(commit) {
+            continue;
+        }
+        let mut in_range = false;
+        for tip in &include {
+            if git_is_ancestor(commit, tip)? {
+                in_range = true;
+                break;
+            }
+        }
+        if in_range {
+            for base in &exclude {
+                if git_is_ancestor(commit, base)? {
+                    in_range = false;
+                    break;
+                }
+            }
+        }
+        if in_range {
+            contained.insert(commit.clone());
+        }
+    }
+    Ok(contained)
+}
+
+/// Returns the full ref name a rev expression like @{upstream} points to or
+/// None if it does not name a ref.
+pub fn git_symbolic_full_name(rev: &str) -> Result<Option<String>> {
+    let args = vec!["rev-parse", "--symbolic-full-name", rev];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .args(&args)
+        .output()?;
+    let name = String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned();
+    Ok((output.status.success() && !name.is_empty()).then_some(name))
+}
+
+/// Returns the name of the remote the branch is tracking, if any.
+pub fn git_upstream_remote(branch: &str) -> Result<Option<String>> {
+    let args = vec!["for-each-ref", "--format=%(upstream:remotename)", branch];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    let remote = String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned();
+    Ok((!remote.is_empty()).then_some(remote))
+}
+
 pub fn git_commit_fixup(
     target: &str,
     mode: FixupMode,
@@ -237,15 +548,17 @@ pub fn git_commit_fixup(
     Ok(())
 }
 
-pub fn git_staged_files() -> Result<Vec<String>> {
-    let files_args = vec![
+pub fn git_staged_files(pathspecs: &[String]) -> Result<Vec<String>> {
+    let mut files_args = vec![
         "--no-pager",
         "diff",
         "--color=never",
         "--name-only",
         "--cached",
         "--no-ext-diff",
+        "--",
     ];
+    files_args.extend(pathspecs.iter().map(|e| e.as_str()));
     let output = Command::new("git")
         .stdout(Stdio::piped())
         .args(&files_args)
@@ -260,6 +573,1003 @@ pub fn git_staged_files() -> Result<Vec<String>> {
         .collect())
 }
 
+/// Returns the files whose changes are smashed: the staged ones or with
+/// --all-changes every modified tracked and optionally untracked file,
+/// limited to the given pathspecs.
+pub fn git_changed_files(config: &Config) -> Result<Vec<String>> {
+    if !config.all_changes {
+        return git_staged_files(&config.pathspecs);
+    }
+
+    let mut commands = vec![vec![
+        "--no-pager",
+        "diff",
+        "--color=never",
+        "--name-only",
+        "--no-ext-diff",
+        "HEAD",
+    ]];
+    if config.include_untracked {
+        commands.push(vec!["ls-files", "--others", "--exclude-standard"]);
+    }
+
+    let mut files = Vec::new();
+    for mut args in commands {
+        args.push("--");
+        args.extend(config.pathspecs.iter().map(|e| e.as_str()));
+        let output = Command::new("git")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(&args)
+            .output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+        }
+        files.extend(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|e| e.to_owned()),
+        );
+    }
+    Ok(files)
+}
+
+/// Diffs the smashed changes against HEAD, see git_changed_files.
+pub fn git_diff_changes(unified: u32, files: &[String], config: &Config) -> Result<String> {
+    if !config.all_changes {
+        return git_diff_cached(unified, files);
+    }
+
+    let unified = format!("--unified={}", unified);
+    let mut args = vec![
+        "--no-pager",
+        "diff",
+        "--color=never",
+        &unified,
+        "--no-prefix",
+        "--no-ext-diff",
+        "HEAD",
+        "--",
+    ];
+    args.extend(files.iter().map(|e| e.as_str()));
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the status letter of every smashed file that git diff reports,
+/// untracked files of --include-untracked are not part of it.
+pub fn git_changes_status(files: &[String], config: &Config) -> Result<Vec<(char, String)>> {
+    let mut args = vec![
+        "--no-pager",
+        "diff",
+        "--color=never",
+        "--name-status",
+        "--no-renames",
+        "--no-ext-diff",
+        match config.all_changes {
+            true => "HEAD",
+            false => "--cached",
+        },
+        "--",
+    ];
+    args.extend(files.iter().map(|e| e.as_str()));
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            let (status, file) = line
+                .split_once('\t')
+                .context("failed to parse diff name status")?;
+            let status = status.chars().next().context("failed to get diff status")?;
+            Ok((status, file.to_string()))
+        })
+        .collect()
+}
+
+/// Stages all working tree changes of tracked files, with untracked files
+/// included if requested.
+pub fn git_stage_changes(
+    include_untracked: bool,
+    pathspecs: &[String],
+    index: Option<&Path>,
+) -> Result<()> {
+    let mut args = vec![
+        "add",
+        match include_untracked {
+            true => "--all",
+            false => "--update",
+        },
+        "--",
+    ];
+    args.extend(pathspecs.iter().map(|e| e.as_str()));
+    let mut cmd = Command::new("git");
+    if let Some(index) = index {
+        cmd.env("GIT_INDEX_FILE", index);
+    }
+    let output = cmd.stderr(Stdio::piped()).args(&args).output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(())
+}
+
+/// Resets the index to HEAD except for the staged state of the paths matching
+/// the pathspecs, so a commit only picks up the changes below them.
+pub fn git_narrow_index(pathspecs: &[String], index: Option<&Path>) -> Result<()> {
+    let git = |args: &[&str], stdin: Option<&str>| -> Result<String> {
+        let mut cmd = Command::new("git");
+        if let Some(index) = index {
+            cmd.env("GIT_INDEX_FILE", index);
+        }
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(args)
+            .spawn()?;
+        if let Some(input) = stdin {
+            child
+                .stdin
+                .as_mut()
+                .context("failed to open stdin")?
+                .write_all(input.as_bytes())?;
+        }
+        drop(child.stdin.take());
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    let mut ls_args = vec!["ls-files", "--stage", "--"];
+    ls_args.extend(pathspecs.iter().map(|e| e.as_str()));
+    let info = git(&ls_args, None)?;
+
+    // Staged deletions have no index entry left, remove them explicitly
+    let mut deleted_args = vec![
+        "--no-pager",
+        "diff",
+        "--cached",
+        "--name-only",
+        "-z",
+        "--no-ext-diff",
+        "--diff-filter=D",
+        "--",
+    ];
+    deleted_args.extend(pathspecs.iter().map(|e| e.as_str()));
+    let deleted = git(&deleted_args, None)?;
+
+    git(&["read-tree", "HEAD"], None)?;
+    git(&["update-index", "--index-info"], Some(&info))?;
+    if !deleted.is_empty() {
+        git(
+            &["update-index", "--force-remove", "-z", "--stdin"],
+            Some(&deleted),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the tree the index would have for the smash: with --all-changes
+/// after staging all working tree changes and with pathspecs narrowed to
+/// them, using a temporary copy of the index.
+pub fn git_write_smash_tree(config: &Config) -> Result<String> {
+    let index = git_path("smash/index")?;
+    if let Some(parent) = index.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let real_index = git_path("index")?;
+    if real_index.exists() {
+        fs::copy(&real_index, &index)?;
+    }
+
+    let result = (|| {
+        if config.all_changes {
+            git_stage_changes(config.include_untracked, &config.pathspecs, Some(&index))?;
+        }
+        if !config.pathspecs.is_empty() {
+            git_narrow_index(&config.pathspecs, Some(&index))?;
+        }
+        let output = Command::new("git")
+            .env("GIT_INDEX_FILE", &index)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(["write-tree"])
+            .output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned())
+    })();
+    fs::remove_file(&index).ok();
+    result
+}
+
+pub fn git_diff_cached(unified: u32, files: &[String]) -> Result<String> {
+    let unified = format!("--unified={}", unified);
+    let mut args = vec![
+        "--no-pager",
+        "diff",
+        "--color=never",
+        &unified,
+        "--no-prefix",
+        "--cached",
+        "--no-ext-diff",
+        "--",
+    ];
+    args.extend(files.iter().map(|e| e.as_str()));
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Diffs the working tree against the index.
+pub fn git_diff_worktree(unified: u32, pathspecs: &[String]) -> Result<String> {
+    let unified = format!("--unified={}", unified);
+    let mut args = vec![
+        "--no-pager",
+        "diff",
+        "--color=never",
+        &unified,
+        "--no-prefix",
+        "--no-ext-diff",
+        "--",
+    ];
+    args.extend(pathspecs.iter().map(|e| e.as_str()));
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Blames the given line ranges of a file and returns the commit for each
+/// line, boundary commits outside of the range are returned as None.
+pub fn git_blame(
+    rev: &str,
+    file: &str,
+    ranges: &[(u32, u32)],
+    options: &[&str],
+) -> Result<Vec<(u32, Option<String>)>> {
+    let mut args = vec![
+        "--no-pager".to_string(),
+        "blame".to_string(),
+        "--line-porcelain".to_string(),
+    ];
+    args.extend(options.iter().map(|e| e.to_string()));
+    for (start, end) in ranges {
+        args.push("-L".to_string());
+        args.push(format!("{},{}", start, end));
+    }
+    args.push(rev.to_string());
+    args.push("--".to_string());
+    args.push(file.to_string());
+
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+
+    let mut lines = Vec::new();
+    let mut current: Option<(u32, String)> = None;
+    let mut boundary = false;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if line.starts_with('\t') {
+            if let Some((number, commit)) = current.take() {
+                lines.push((number, (!boundary).then_some(commit)));
+            }
+            boundary = false;
+        } else if line == "boundary" {
+            boundary = true;
+        } else if current.is_none() {
+            let mut fields = line.split(' ');
+            let commit = fields.next().context("failed to get blame commit")?;
+            let number = fields
+                .nth(1)
+                .context("failed to get blame line number")?
+                .parse::<u32>()
+                .context("failed to parse blame line number")?;
+            current = Some((number, commit.to_string()));
+        }
+    }
+
+    Ok(lines)
+}
+
+pub fn git_line_count(rev: &str, file: &str) -> Result<u32> {
+    let object = format!("{}:{}", rev, file);
+    let args = vec!["cat-file", "blob", &object];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().count() as u32)
+}
+
+/// Resolves each rev to the commit it names in one go, revs that don't name
+/// a commit map to None.
+pub fn git_resolve_commits(revs: &[String]) -> Result<Vec<(String, Option<String>)>> {
+    let args = vec!["cat-file", "--batch-check=%(objectname)"];
+    let mut cmd = Command::new("git")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .spawn()?;
+    // cat-file answers every line as it reads it, feed it while reading
+    let mut stdin = cmd
+        .stdin
+        .take()
+        .context("failed to acquire stdin from git cat-file command")?;
+    let input = revs
+        .iter()
+        .map(|e| format!("{}^{{commit}}\n", e))
+        .collect::<String>();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = cmd.wait_with_output()?;
+    writer
+        .join()
+        .ok()
+        .context("failed to write to git cat-file")??;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(revs
+        .iter()
+        .cloned()
+        .zip(String::from_utf8_lossy(&output.stdout).lines().map(|e| {
+            match e.ends_with(" missing") || e.ends_with(" ambiguous") {
+                true => None,
+                false => Some(e.to_string()),
+            }
+        }))
+        .collect())
+}
+
+pub fn git_write_tree() -> Result<String> {
+    let args = vec!["write-tree"];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
+
+pub fn git_read_tree(tree: &str) -> Result<()> {
+    let args = vec!["read-tree", tree];
+    let output = Command::new("git")
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(())
+}
+
+pub fn git_apply_cached(patch: &str) -> Result<()> {
+    let args = vec!["apply", "--cached", "--unidiff-zero", "-"];
+    let mut cmd = Command::new("git")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .spawn()?;
+    cmd.stdin
+        .as_mut()
+        .context("failed to acquire stdin from git apply command")?
+        .write_all(patch.as_bytes())?;
+    let output = cmd.wait_with_output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(())
+}
+
+pub fn git_merge_base_octopus(revs: &[String]) -> Result<String> {
+    let mut args = vec!["merge-base", "--octopus"];
+    args.extend(revs.iter().map(|e| e.as_str()));
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
+
+/// Runs git log on the given revs read from stdin in the same order, without
+/// walking their history.
+pub fn git_log_no_walk(args: &[&str], revs: &[String]) -> Result<String> {
+    let mut log_args = vec!["--no-pager", "log", "--no-walk=unsorted", "--stdin"];
+    log_args.extend(args);
+    let mut cmd = Command::new("git")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&log_args)
+        .spawn()?;
+    // git reads all revs from stdin before walking, so the pipe can't stall
+    writeln!(
+        cmd.stdin
+            .as_mut()
+            .context("failed to acquire stdin from git log command")?,
+        "{}",
+        revs.join("\n")
+    )?;
+    let output = cmd.wait_with_output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn git_commit_subjects(revs: &[String]) -> Result<Vec<TodoCommit>> {
+    if revs.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(git_log_no_walk(&["--format=%H %s"], revs)?
+        .lines()
+        .map(|line| {
+            let (commit, subject) = line.split_once(' ').unwrap_or((line, ""));
+            TodoCommit::new(commit, subject)
+        })
+        .collect())
+}
+
+pub fn git_commit_times(revs: &[String]) -> Result<Vec<(String, i64)>> {
+    if revs.is_empty() {
+        return Ok(Vec::new());
+    }
+    git_log_no_walk(&["--format=%H %ct"], revs)?
+        .lines()
+        .map(|line| {
+            let (commit, time) = line
+                .split_once(' ')
+                .context("failed to split commit time")?;
+            let time = time
+                .parse::<i64>()
+                .with_context(|| format!("failed to parse commit time '{}'", time))?;
+            Ok((commit.to_string(), time))
+        })
+        .collect()
+}
+
+/// Lists the commits in the range that changed any of the files, together
+/// with the number of those files each commit touched, newest first.
+/// Following renames is only supported by git for a single file, so the
+/// history of every renamed file is followed separately and merged.
+pub fn git_file_revs(
+    files: &[String],
+    range: &str,
+    max_count: u32,
+    follow: bool,
+) -> Result<Vec<(String, u32)>> {
+    let renamed = match follow && files.len() > 1 {
+        true => git_renamed_files(files, range)?,
+        false => Vec::new(),
+    };
+    if renamed.is_empty() || files.len() == 1 {
+        let follow = follow && files.len() == 1;
+        return Ok(git_log_file_revs(files, range, max_count, follow)?
+            .into_iter()
+            .map(|(commit, _, count)| (commit, count))
+            .collect());
+    }
+
+    let others = files
+        .iter()
+        .filter(|e| !renamed.contains(e))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut logs = renamed
+        .iter()
+        .map(|e| git_log_file_revs(slice::from_ref(e), range, max_count, true))
+        .collect::<Result<Vec<_>>>()?;
+    if !others.is_empty() {
+        logs.push(git_log_file_revs(&others, range, max_count, false)?);
+    }
+
+    let mut revs: Vec<(String, i64, u32)> = Vec::new();
+    for (commit, time, count) in logs.into_iter().flatten() {
+        match revs.iter_mut().find(|e| e.0 == commit) {
+            Some(rev) => rev.2 += count,
+            None => revs.push((commit, time, count)),
+        }
+    }
+    revs.sort_by_key(|(_, time, _)| Reverse(*time));
+    if max_count > 0 {
+        revs.truncate(max_count as usize);
+    }
+    Ok(revs
+        .into_iter()
+        .map(|(commit, _, count)| (commit, count))
+        .collect())
+}
+
+/// Returns the files that a commit of the range created by renaming another
+/// file, only their history gains from following renames.
+fn git_renamed_files(files: &[String], range: &str) -> Result<Vec<String>> {
+    let mut args = vec![
+        "--no-pager",
+        "log",
+        "--color=never",
+        "--diff-filter=A",
+        "--format=%H",
+        range,
+        "--",
+    ];
+    args.extend(files.iter().map(|e| e.as_str()));
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    let adds = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|e| e.to_owned())
+        .collect::<Vec<_>>();
+    if adds.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let renames = git_log_no_walk(
+        &[
+            "--color=never",
+            "--format=",
+            "--find-renames",
+            "--diff-filter=R",
+            "--name-status",
+            "-z",
+        ],
+        &adds,
+    )?;
+    // Records are the status, the old and the new path, separated by NUL
+    let paths = renames
+        .split('\0')
+        .map(|e| e.trim_start_matches('\n'))
+        .filter(|e| !e.is_empty())
+        .collect::<Vec<_>>();
+    Ok(files
+        .iter()
+        .filter(|file| {
+            paths
+                .chunks(3)
+                .any(|e| e.len() == 3 && e[0].starts_with('R') && e[2] == file.as_str())
+        })
+        .cloned()
+        .collect())
+}
+
+fn git_log_file_revs(
+    files: &[String],
+    range: &str,
+    max_count: u32,
+    follow: bool,
+) -> Result<Vec<(String, i64, u32)>> {
+    let max_count_arg = format!("-{}", max_count);
+    let mut args = vec![
+        "--no-pager",
+        "log",
+        "--color=never",
+        "--no-ext-diff",
+        "--name-only",
+        "--invert-grep",
+        "--extended-regexp",
+        "--grep",
+        "^(fixup|squash)! .*$",
+        "--format=%x00%H %ct",
+        range,
+    ];
+    if max_count > 0 {
+        args.push(&max_count_arg);
+    }
+    if follow {
+        args.push("--follow");
+    }
+    args.push("--");
+    args.extend(files.iter().map(|e| e.as_str()));
+
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .skip(1)
+        .map(|record| {
+            let mut lines = record.lines().filter(|e| !e.is_empty());
+            let header = lines.next().context("failed to get commit of file rev")?;
+            let (commit, time) = header.split_once(' ').unwrap_or((header, "0"));
+            let time = time
+                .parse::<i64>()
+                .with_context(|| format!("failed to parse commit time '{}'", time))?;
+            Ok((commit.to_string(), time, lines.count() as u32))
+        })
+        .collect()
+}
+
+pub fn git_commit_tree(tree: &str, parent: &str, message: &str) -> Result<String> {
+    let args = vec!["commit-tree", tree, "-p", parent, "-m", message];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
+
+/// Author and message of a commit that a rewrite carries over.
+pub struct CommitInfo {
+    pub author_name: String,
+    pub author_email: String,
+    pub author_date: String,
+    pub message: String,
+}
+
+pub fn git_commit_info(rev: &str) -> Result<CommitInfo> {
+    let args = vec![
+        "--no-pager",
+        "log",
+        "-1",
+        "--format=%an%x00%ae%x00%aI%x00%B",
+        rev,
+    ];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.splitn(4, '\0');
+    let mut field = || {
+        fields
+            .next()
+            .map(|e| e.to_string())
+            .with_context(|| format!("failed to parse commit info of {}", rev))
+    };
+    Ok(CommitInfo {
+        author_name: field()?,
+        author_email: field()?,
+        author_date: field()?,
+        // Strip the terminator log appends to the raw message
+        message: field()?.strip_suffix('\n').unwrap_or_default().to_string(),
+    })
+}
+
+/// Creates a commit with the author and exact message of the given commit
+/// info, the committer is the current user like in a rebase.
+pub fn git_commit_tree_as(
+    tree: &str,
+    parent: Option<&str>,
+    info: &CommitInfo,
+    gpg_sign: &Option<String>,
+) -> Result<String> {
+    let mut args = vec!["commit-tree", tree, "-F", "-"];
+    if let Some(parent) = parent {
+        args.extend(["-p", parent]);
+    }
+    if let Some(gpg_sign) = gpg_sign {
+        args.push(gpg_sign);
+    }
+    let mut cmd = Command::new("git")
+        .env("GIT_AUTHOR_NAME", &info.author_name)
+        .env("GIT_AUTHOR_EMAIL", &info.author_email)
+        .env("GIT_AUTHOR_DATE", &info.author_date)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .spawn()?;
+    cmd.stdin
+        .as_mut()
+        .context("failed to acquire stdin from git commit-tree command")?
+        .write_all(info.message.as_bytes())?;
+    let output = cmd.wait_with_output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
+
+pub fn git_update_ref(reference: &str, rev: &str, message: &str) -> Result<()> {
+    let args = vec![
+        "update-ref",
+        "--create-reflog",
+        "-m",
+        message,
+        reference,
+        rev,
+    ];
+    let output = Command::new("git")
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(())
+}
+
+/// Returns the tips and full names of the local branches merged into HEAD
+/// other than the checked out branch.
+pub fn git_merged_branches() -> Result<Vec<(String, String)>> {
+    let args = vec![
+        "for-each-ref",
+        "--format=%(objectname) %(refname)",
+        "--merged",
+        "HEAD",
+        "refs/heads/",
+    ];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    let head = git_symbolic_head()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|e| e.split_once(' '))
+        .filter(|(_, name)| head.as_deref() != Some(*name))
+        .map(|(tip, name)| (tip.to_string(), name.to_string()))
+        .collect())
+}
+
+/// Returns the parents of every commit reachable from the revs, excluding
+/// the history of the given commit.
+pub fn git_rev_parents(
+    revs: &[String],
+    exclude: Option<&str>,
+) -> Result<HashMap<String, Vec<String>>> {
+    let exclude = exclude.map(|e| format!("^{}", e));
+    let mut args = vec!["rev-list", "--parents"];
+    args.extend(revs.iter().map(|e| e.as_str()));
+    args.extend(exclude.as_deref());
+    args.push("--");
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut commits = line.split(' ').map(|e| e.to_string());
+            Some((commits.next()?, commits.collect()))
+        })
+        .collect())
+}
+
+/// Lists the local branches besides the checked out one whose heads sit
+/// between the given commit and HEAD.
+pub fn git_stacked_branches(rev: &str) -> Result<Vec<String>> {
+    let args = vec![
+        "for-each-ref",
+        "--format=%(refname)",
+        "--contains",
+        rev,
+        "--merged",
+        "HEAD",
+        "refs/heads/",
+    ];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    let head = git_symbolic_head()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|e| head.as_deref() != Some(*e))
+        .map(|e| e.to_string())
+        .collect())
+}
+
+/// Returns the full name of the checked out branch or None if HEAD is
+/// detached.
+pub fn git_symbolic_head() -> Result<Option<String>> {
+    let args = vec!["symbolic-ref", "--quiet", "HEAD"];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        match output.status.code() {
+            Some(1) => return Ok(None),
+            _ => bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end()),
+        }
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned(),
+    ))
+}
+
+pub fn git_commit_message(rev: &str) -> Result<String> {
+    let args = vec!["--no-pager", "log", "-1", "--format=%B", rev];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn git_reset_soft(rev: &str, message: &str) -> Result<()> {
+    let args = vec!["reset", "--soft", "--quiet", rev];
+    let output = Command::new("git")
+        .env("GIT_REFLOG_ACTION", message)
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(())
+}
+
+pub fn git_path(path: &str) -> Result<PathBuf> {
+    let args = vec!["rev-parse", "--git-path", path];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+    ))
+}
+
+/// Copy of the index that all git commands of this process use instead of
+/// the real one until it is dropped.
+pub struct IndexCopy {
+    path: PathBuf,
+}
+
+impl IndexCopy {
+    pub fn create() -> Result<Self> {
+        let path = env::current_dir()?.join(git_path("smash/index-copy")?);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let real_index = git_path("index")?;
+        if real_index.exists() {
+            fs::copy(&real_index, &path)?;
+        }
+        env::set_var("GIT_INDEX_FILE", &path);
+        Ok(Self { path })
+    }
+}
+
+impl Drop for IndexCopy {
+    fn drop(&mut self) {
+        env::remove_var("GIT_INDEX_FILE");
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+pub fn git_rebase_in_progress() -> Result<bool> {
+    for state in ["rebase-merge", "rebase-apply"] {
+        if git_path(state)?.exists() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Merges two commits without touching the index or working tree and returns
+/// the resulting tree together with the conflicting files.
+pub fn git_merge_tree(ours: &str, theirs: &str) -> Result<(String, Vec<String>)> {
+    let args = vec![
+        "merge-tree",
+        "--write-tree",
+        "--name-only",
+        "--no-messages",
+        ours,
+        theirs,
+    ];
+    let output = Command::new("git")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .args(&args)
+        .output()?;
+    if !matches!(output.status.code(), Some(0 | 1)) {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let tree = lines
+        .next()
+        .context("failed to get tree from merge-tree")?
+        .to_string();
+    let mut conflicts = Vec::<String>::new();
+    for file in lines.take_while(|e| !e.is_empty()) {
+        if !conflicts.iter().any(|e| e == file) {
+            conflicts.push(file.to_string());
+        }
+    }
+    Ok((tree, conflicts))
+}
+
 pub fn git_version() -> Result<Version> {
     let args = vec!["version"];
     let output = Command::new("git")
@@ -11,29 +11,55 @@ mod git;
 use config::*;
 mod config;
 
-use hash::*;
+mod diff;
+
+use pick::*;
+mod pick;
+
+use candidate::*;
+mod candidate;
+
+use absorb::*;
+mod absorb;
+
+use menu::*;
+mod menu;
+
+use plan::*;
+mod plan;
+
+use predict::*;
+mod predict;
+
+use rebase::*;
+mod rebase;
+
+use protect::*;
+mod protect;
+
+use resume::*;
+mod resume;
+
+mod todo;
+
+use undo::*;
+mod undo;
+
 mod hash;
 
-use std::collections::HashSet;
-use std::hash::BuildHasherDefault;
-use std::io::{BufRead, BufReader, Write};
-use std::process::{exit, Child, Command, Stdio};
-use std::{env, io, str};
+#[cfg(feature = "tui")]
+use tui::*;
+#[cfg(feature = "tui")]
+mod tui;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{exit, Child};
+use std::{env, io, slice};
 
-use ahash::RandomState;
 use clap::Parser;
-use regex::Regex;
 
-struct MenuCommand {
-    command: String,
-    args: Vec<String>,
-}
-
-impl MenuCommand {
-    const fn new(command: String, args: Vec<String>) -> Self {
-        Self { command, args }
-    }
-}
+const EXIT_NO_AUTO_TARGET: i32 = 3;
 
 fn run(args: Args) -> Result<()> {
     if let Some(SubCommand::Completions(completions)) = args.subcommand {
@@ -41,22 +67,24 @@ fn run(args: Args) -> Result<()> {
         return Ok(());
     }
 
-    let hasher = RandomState::new();
-    let mut unique = HashSet::<u64, BuildHasherDefault<IdentityHasher>>::default();
-
     let config = Config::load(&args)?;
 
     let toplevel = git_toplevel().context("failed to get git toplevel path")?;
     env::set_current_dir(toplevel)?;
 
-    let mut staged_files = git_staged_files()?;
-    if staged_files.is_empty() {
-        writeln!(
-            io::stderr(),
-            "Changes not staged for commit\nUse git add -p to stage changed files"
-        )
-        .ok();
-        exit(1);
+    if args.continue_smash {
+        return smash_continue();
+    }
+    if args.abort {
+        return smash_abort();
+    }
+
+    if smash_in_progress()? && config.mode != DisplayMode::List && !config.dry_run {
+        bail!("A smash is in progress\nRun git smash --continue or git smash --abort first");
+    }
+
+    if matches!(args.subcommand, Some(SubCommand::Undo)) {
+        return undo();
     }
 
     let range = git_rev_range(&config)?.ok_or_else(|| {
@@ -68,143 +96,197 @@ fn run(args: Args) -> Result<()> {
         bail!("Ambiguous argument '{}': unknown revision", range)
     }
 
-    if let Some(target) = config.commit {
-        match git_rev_parse(&target) {
+    if matches!(args.subcommand, Some(SubCommand::Rebase)) {
+        return rebase_pending(&config, &range);
+    }
+
+    // A dry run stages the picked hunks into a copy of the index
+    let _index_copy = match config.patch && config.dry_run {
+        true => Some(IndexCopy::create().context("failed to copy the index")?),
+        false => None,
+    };
+    if config.patch {
+        pick_hunks(&config, &range)?;
+    }
+
+    let staged_files = git_changed_files(&config)?;
+    if staged_files.is_empty() && config.all_changes {
+        writeln!(io::stderr(), "No changes found to smash").ok();
+        exit(1);
+    }
+    if staged_files.is_empty() && !config.pathspecs.is_empty() {
+        writeln!(
+            io::stderr(),
+            "No staged changes found below {}",
+            config.pathspecs.join(" ")
+        )
+        .ok();
+        exit(1);
+    }
+    if staged_files.is_empty() {
+        writeln!(
+            io::stderr(),
+            "Changes not staged for commit\nUse git add -p or git smash -p to stage changed files"
+        )
+        .ok();
+        exit(1);
+    }
+
+    if matches!(args.subcommand, Some(SubCommand::Absorb)) {
+        return absorb(&config, &staged_files, &range);
+    }
+
+    if let Some(target) = &config.commit {
+        match git_rev_parse(target) {
             Err(_) => bail!("Ambiguous argument '{}': unknown revision", target),
-            Ok(target) => {
-                git_commit_fixup(
-                    &target,
-                    config.fixup_mode,
-                    &config.gpg_sign_option,
-                    &config.verify_option,
-                )?;
-
-                if config.auto_rebase {
-                    git_rebase(
-                        &target,
-                        config.interactive,
-                        &config.gpg_sign_option,
-                        &config.verify_option,
-                    )?;
-                }
+            Ok(target) => return smash(&target, &config),
+        }
+    }
 
+    let mut candidates = collect_candidates(&config, &staged_files, &range)?;
+
+    if config.auto && config.mode != DisplayMode::List {
+        if let Some(candidate) = auto_target(&candidates, config.auto_threshold) {
+            if config.mode == DisplayMode::Select {
+                guard_protected(&candidate.commit, &config)?;
+                writeln!(io::stdout(), "{}", &candidate.commit).ok();
                 return Ok(());
             }
+            return smash(&candidate.commit, &config);
+        }
+        if !has_tty() {
+            writeln!(
+                io::stderr(),
+                "No commit holds {}% of the blamed lines\nLower --auto-threshold or pick a target interactively",
+                config.auto_threshold
+            )
+            .ok();
+            exit(EXIT_NO_AUTO_TARGET);
         }
     }
 
-    let mut cmd_sk = match config.mode {
+    if config.max_count > 0 {
+        candidates.truncate(config.max_count as usize);
+    }
+    mark_conflicts(&mut candidates, &config)?;
+    mark_branches(&mut candidates, &config)?;
+
+    let menu = match config.mode {
         DisplayMode::List => None,
-        _ => Some(spawn_menu(&config).context("failed to spawn menu command")?),
+        _ => Some(resolve_menu(&config)?),
     };
+    let color = menu.as_ref().is_none_or(|menu| menu.color());
+    let targets = format_candidates(&candidates, &config, color)?;
 
-    if config.recent > 0 {
-        for rev in git_rev_list(&range, config.recent)
-            .with_context(|| format!("failed to get rev-list for {}", range))?
-        {
-            if !unique.insert(hash(&hasher, &rev)) {
-                continue;
-            }
-
-            let target = format_target(&rev, &config.format, &config.source_label_recent)?;
+    let mut cmd_sk = match menu {
+        Some(Menu::Command(menu)) => {
+            Some(spawn_menu(&menu).context("failed to spawn menu command")?)
+        }
+        #[cfg(feature = "tui")]
+        Some(Menu::Builtin) => {
+            let selection = select_target_builtin(&targets, &config)?.unwrap_or_default();
+            return smash_selection(selection.as_bytes(), &config);
+        }
+        None => None,
+    };
 
-            if !process_target(&target, &config.mode, &mut cmd_sk) {
-                break;
-            }
+    for target in targets {
+        if !process_target(&target, &config.mode, &mut cmd_sk) {
+            break;
         }
     }
 
-    if config.blame {
-        let commits_from_blame = match config.blame {
-            true => get_commits_from_blame(&staged_files, &range)?,
-            false => Vec::new(),
-        };
-        for rev in commits_from_blame {
-            if !unique.insert(hash(&hasher, &rev)) {
-                continue;
-            }
+    if let Some(cmd_sk) = cmd_sk {
+        let output = cmd_sk.wait_with_output()?;
+        smash_selection(&output.stdout, &config)?;
+    }
+
+    Ok(())
+}
 
-            let target = format_target(&rev, &config.format, &config.source_label_blame)?;
+fn smash_selection(selection: &[u8], config: &Config) -> Result<()> {
+    let target = select_target(selection)?;
 
-            if !process_target(&target, &config.mode, &mut cmd_sk) {
-                break;
-            }
-        }
+    if target.is_empty() {
+        return Ok(());
     }
 
-    if config.files {
-        let mut cmd_file_revs = spawn_file_revs(
-            &mut staged_files,
-            &config.format,
-            &range,
-            config.max_count,
-            &config.source_label_files,
-        )?;
-
-        let stdout = cmd_file_revs
-            .stdout
-            .as_mut()
-            .context("failed to acquire stdout from git log command")?;
-        let stdout_reader = BufReader::new(stdout);
-        let stdout_lines = stdout_reader.split(b'\n');
-
-        for target in stdout_lines {
-            let target = target.context("failed to read bytes from stream")?;
-            let target = String::from_utf8_lossy(&target);
-            let target = target.trim_end();
-            let mut target = target.splitn(2, ' ');
-            let target_hash = target.next().context("failed to extract target hash")?;
-            let target = target.next().context("failed to extract target")?;
-
-            if !unique.insert(hash(&hasher, &target_hash)) {
-                continue;
-            }
+    if !is_valid_git_rev(&target)? {
+        bail!("Selected commit '{}' not found\nPossibly --format or smash.format doesn't return a hash", target);
+    }
 
-            if !process_target(target, &config.mode, &mut cmd_sk) {
-                break;
-            }
-        }
+    if config.mode == DisplayMode::Select {
+        guard_protected(&target, config)?;
+        writeln!(io::stdout(), "{}", &target).ok();
+        return Ok(());
+    }
+
+    smash(&target, config)
+}
 
-        cmd_file_revs.kill()?;
+fn smash(target: &str, config: &Config) -> Result<()> {
+    if config.dry_run {
+        return print_plan(&[target.to_string()], config.fixup_mode, config);
     }
 
-    if let Some(cmd_sk) = cmd_sk {
-        let output = cmd_sk.wait_with_output()?;
-        let target = select_target(output.stdout.as_ref())?;
+    guard_protected(target, config)?;
 
-        if target.is_empty() {
-            return Ok(());
+    if config.require_clean && config.auto_rebase {
+        if let Some(conflict) = predict_conflict(target, config.fixup_mode, config)? {
+            bail!(
+                "Smashing into {} would conflict at {}\nResolve it manually or drop --require-clean",
+                target,
+                conflict
+            );
         }
+    }
 
-        if !is_valid_git_rev(&target)? {
-            bail!("Selected commit '{}' not found\nPossibly --format or smash.format doesn't return a hash", target);
-        }
+    record_smash_state(&format!("smash into {}", target))?;
 
-        if config.mode == DisplayMode::Select {
-            writeln!(io::stdout(), "{}", &target).ok();
-            return Ok(());
-        }
+    let reword = matches!(config.fixup_mode, FixupMode::Reword);
+    if config.all_changes && !reword {
+        git_stage_changes(config.include_untracked, &config.pathspecs, None)
+            .context("failed to stage changes")?;
+    }
 
-        git_commit_fixup(
-            &target,
-            config.fixup_mode,
-            &config.gpg_sign_option,
-            &config.verify_option,
-        )?;
-
-        if config.auto_rebase {
-            git_rebase(
-                &target,
-                config.interactive,
-                &config.gpg_sign_option,
-                &config.verify_option,
-            )?;
+    // Commit only the staged changes below the pathspecs, the original index
+    // equals the new HEAD plus everything outside of them afterwards
+    let index = match config.pathspecs.is_empty() || reword {
+        true => None,
+        false => {
+            let tree = git_write_tree().context("failed to write index tree")?;
+            git_narrow_index(&config.pathspecs, None).context("failed to narrow index")?;
+            Some(tree)
         }
+    };
+    let result = git_commit_fixup(
+        target,
+        config.fixup_mode,
+        &config.gpg_sign_option,
+        &config.verify_option,
+    );
+    if let Some(tree) = &index {
+        git_read_tree(tree).context("failed to restore index")?;
+    }
+    result?;
+
+    if config.auto_rebase {
+        let fixup = git_rev_parse("HEAD").context("failed to rev parse HEAD")?;
+        // The changes outside of the pathspecs stay staged
+        rebase(target, slice::from_ref(&fixup), index.as_deref(), config)?;
     }
 
     Ok(())
 }
 
+fn has_tty() -> bool {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .is_ok()
+}
+
 fn process_target(target: &str, mode: &DisplayMode, cmd_sk: &mut Option<Child>) -> bool {
     match mode {
         DisplayMode::List => {
@@ -226,202 +308,6 @@ fn process_target(target: &str, mode: &DisplayMode, cmd_sk: &mut Option<Child>)
     true
 }
 
-fn get_commits_from_blame(staged_files: &[String], range: &str) -> Result<Vec<String>> {
-    let mut diff_args = vec![
-        "--no-pager",
-        "diff",
-        "--color=never",
-        "--unified=1",
-        "--no-prefix",
-        "--cached",
-        "--no-ext-diff",
-    ]
-    .into_iter()
-    .map(|e| e.to_string())
-    .collect::<Vec<_>>();
-    diff_args.push("--".to_string());
-    let mut staged_files = staged_files.to_owned();
-    diff_args.append(&mut staged_files);
-
-    let cmd_diff = Command::new("git")
-        .args(&diff_args)
-        .stdout(Stdio::piped())
-        .spawn()?;
-
-    let output = cmd_diff.wait_with_output()?;
-
-    let re_split = Regex::new(r"(?m)^diff ")?;
-    let re_file = Regex::new(r"(?m)^--- (.+)")?;
-    let re_chunk = Regex::new(r"(?m)^@@ -([0-9]+)(,([0-9]+))? ([^ ]+) @@")?;
-
-    let diff = String::from_utf8_lossy(&output.stdout);
-
-    let mut commits: Vec<String> = Vec::new();
-
-    for split in re_split.split(&diff).skip(1) {
-        let file = re_file
-            .captures(split)
-            .context("failed to match file in chunk")?;
-        let file = file.get(1).context("failed to get file group")?.as_str();
-        if file == "/dev/null" {
-            continue;
-        }
-
-        let mut blame_args = vec![
-            "--no-pager".to_string(),
-            "blame".to_string(),
-            "--no-abbrev".to_string(),
-            "-s".to_string(),
-        ];
-
-        for chunks in re_chunk.captures_iter(split) {
-            let offset = chunks
-                .get(1)
-                .context("failed to get offset group")?
-                .as_str();
-            let length = chunks
-                .get(3)
-                .map(|m| m.as_str())
-                .context("failed to get length group")
-                .unwrap_or("1");
-
-            let location = format!("{},+{}", offset, length);
-            blame_args.push("-L".to_string());
-            blame_args.push(location);
-        }
-
-        blame_args.push(range.to_string());
-        blame_args.push("--".to_string());
-        blame_args.push(file.to_string());
-
-        let blame_output = Command::new("git")
-            .args(blame_args)
-            .stdout(Stdio::piped())
-            .output()?;
-
-        let blame_output = String::from_utf8_lossy(&blame_output.stdout);
-        let split_commits: Vec<_> = blame_output
-            .lines()
-            .filter_map(|e| e.split_whitespace().next())
-            .collect();
-        for hash in split_commits {
-            if hash.starts_with('^') {
-                continue;
-            }
-            commits.push(hash.into());
-        }
-    }
-
-    Ok(commits)
-}
-
-fn spawn_file_revs(
-    staged_files: &mut Vec<String>,
-    format: &str,
-    range: &str,
-    max_count: u32,
-    source_format: &str,
-) -> Result<Child> {
-    let format = format.replace("%(smash:source)", source_format);
-    let mut file_revs_args = vec![
-        "--no-pager",
-        "log",
-        "--color",
-        "--invert-grep",
-        "--extended-regexp",
-        "--grep",
-        "^(fixup|squash)! .*$",
-        format!("--format=%H {}", format).as_str(),
-        range,
-    ]
-    .into_iter()
-    .map(|e| e.to_string())
-    .collect::<Vec<_>>();
-    if max_count > 0 {
-        file_revs_args.push(format!("-{}", max_count));
-    }
-    file_revs_args.push("--".to_string());
-    file_revs_args.append(staged_files);
-
-    Ok(Command::new("git")
-        .args(&file_revs_args)
-        .stdout(Stdio::piped())
-        .spawn()?)
-}
-
-fn format_target(commit: &str, format: &str, source_format: &str) -> Result<String> {
-    let format = format.replace("%(smash:source)", source_format);
-    let format = format!("--format={}", format);
-    let args = vec!["--no-pager", "log", "--color", "-1", &format, commit];
-    let output = Command::new("git")
-        .stdout(Stdio::piped())
-        .args(&args)
-        .output()?;
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
-}
-
-fn spawn_menu(config: &Config) -> Result<Child> {
-    let menu = resolve_menu_command(config)?;
-    Ok(Command::new(menu.command)
-        .args(menu.args)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .env("DFT_COLOR", "always")
-        .spawn()?)
-}
-
-fn select_target(line: &[u8]) -> Result<String> {
-    let cow = String::from_utf8_lossy(line);
-    Ok(cow
-        .split(' ')
-        .next()
-        .context("failed to split first part of the target")?
-        .into())
-}
-
-fn resolve_command(command: &str) -> Result<Option<String>> {
-    let output = Command::new("sh")
-        .stdout(Stdio::piped())
-        .args(vec!["-c", format!("command -v {}", &command).as_ref()])
-        .output()?;
-    if !output.status.success() {
-        return Ok(None);
-    }
-    Ok(Some(
-        String::from_utf8_lossy(&output.stdout).trim().to_owned(),
-    ))
-}
-
-fn resolve_menu_command(config: &Config) -> Result<MenuCommand> {
-    let pipe = config.pager.as_ref().map_or_else(
-        || "".to_string(),
-        |pager| {
-            if pager == "delta" {
-                "| delta".to_string()
-            } else {
-                "".to_string()
-            }
-        },
-    );
-    let ext_diff = config.ext_diff.clone().unwrap_or_default();
-    let show_args = [ext_diff].join(" ");
-
-    let fuzzy_args = vec![
-        "--ansi".to_string(),
-        "--bind".to_string(),
-        "ctrl-f:preview-page-down,ctrl-b:preview-page-up".to_string(),
-        format!("--preview-window={}", &config.preview_window),
-        "--preview".to_string(),
-        format!("git show --stat --patch --color {show_args} {{1}}{pipe}"),
-    ];
-    for cmd in &[("fzf", &fuzzy_args)] {
-        if let Some(bin) = resolve_command(cmd.0)? {
-            return Ok(MenuCommand::new(bin, cmd.1.to_owned()));
-        }
-    }
-    bail!("Can't find any supported fuzzy matcher or menu command\nPlease install fzf or configure one with smash.menu");
-}
-
 fn main() {
     let args = Args::parse();
 
@@ -0,0 +1,276 @@
+use crate::config::Config;
+use crate::errors::*;
+use crate::git::shell_quote;
+
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+
+const BUILTIN_MENU: &str = "builtin";
+
+const PREVIEW_PLACEHOLDER: &str = "%(smash:preview)";
+const PREVIEW_WINDOW_PLACEHOLDER: &str = "%(smash:preview-window)";
+
+const FUZZY_ARGS: &[&str] = &[
+    "--ansi",
+    "--bind",
+    "ctrl-f:preview-page-down,ctrl-b:preview-page-up",
+    "--preview-window=%(smash:preview-window)",
+    "--preview",
+    "%(smash:preview)",
+];
+
+struct MenuProfile {
+    name: &'static str,
+    args: &'static [&'static str],
+    detect: bool,
+}
+
+/// Known menu commands with the flags they need to act as a line picker.
+/// Skim is not detected automatically as some versions hang on our input.
+const MENU_PROFILES: &[MenuProfile] = &[
+    MenuProfile {
+        name: "fzf",
+        args: FUZZY_ARGS,
+        detect: true,
+    },
+    MenuProfile {
+        name: "sk",
+        args: FUZZY_ARGS,
+        detect: false,
+    },
+    MenuProfile {
+        name: "fzy",
+        args: &["--lines=20"],
+        detect: true,
+    },
+    MenuProfile {
+        name: "peco",
+        args: &[],
+        detect: true,
+    },
+    MenuProfile {
+        name: "gum",
+        args: &["filter", "--placeholder=Select target commit"],
+        detect: true,
+    },
+    MenuProfile {
+        name: "rofi",
+        args: &["-dmenu", "-i", "-p", "smash"],
+        detect: false,
+    },
+    MenuProfile {
+        name: "dmenu",
+        args: &["-i", "-l", "20", "-p", "smash"],
+        detect: false,
+    },
+];
+
+pub struct MenuCommand {
+    command: String,
+    args: Vec<String>,
+    /// Whether the menu renders ANSI colors of the listed targets
+    pub color: bool,
+}
+
+impl MenuCommand {
+    fn new(command: String, args: Vec<String>) -> Self {
+        let color = args.iter().any(|e| e == "--ansi");
+        Self {
+            command,
+            args,
+            color,
+        }
+    }
+}
+
+pub enum Menu {
+    Command(MenuCommand),
+    #[cfg(feature = "tui")]
+    Builtin,
+}
+
+impl Menu {
+    pub const fn color(&self) -> bool {
+        match self {
+            Self::Command(menu) => menu.color,
+            #[cfg(feature = "tui")]
+            Self::Builtin => false,
+        }
+    }
+}
+
+pub fn spawn_menu(menu: &MenuCommand) -> Result<Child> {
+    Ok(Command::new(&menu.command)
+        .args(&menu.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .env("DFT_COLOR", "always")
+        .spawn()?)
+}
+
+pub fn select_target(line: &[u8]) -> Result<String> {
+    let cow = String::from_utf8_lossy(line);
+    Ok(cow
+        .split(' ')
+        .next()
+        .context("failed to split first part of the target")?
+        .trim()
+        .into())
+}
+
+fn resolve_command(command: &str) -> Result<Option<String>> {
+    let output = Command::new("sh")
+        .stdout(Stdio::piped())
+        .args(vec![
+            "-c",
+            format!("command -v {}", shell_quote(command)).as_ref(),
+        ])
+        .output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_owned(),
+    ))
+}
+
+/// Splits a command line into words honoring single and double quotes as
+/// well as backslash escapes.
+fn split_args(line: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                let escaped = chars
+                    .next()
+                    .context("trailing backslash in smash.menuArgs")?;
+                current.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), c) => current.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => {
+                if let Some(arg) = current.take() {
+                    args.push(arg);
+                }
+            }
+            (None, c) => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("Unterminated quote in smash.menuArgs");
+    }
+    if let Some(arg) = current {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn preview_command(config: &Config) -> String {
+    let pipe = config.pager.as_ref().map_or_else(
+        || "".to_string(),
+        |pager| {
+            if pager == "delta" {
+                "| delta".to_string()
+            } else {
+                "".to_string()
+            }
+        },
+    );
+    let ext_diff = config.ext_diff.clone().unwrap_or_default();
+    let show_args = [ext_diff].join(" ");
+    format!("git show --stat --patch --color {show_args} {{1}}{pipe}")
+}
+
+fn expand_args<T: AsRef<str>>(args: &[T], config: &Config) -> Vec<String> {
+    let preview = preview_command(config);
+    args.iter()
+        .map(|arg| {
+            arg.as_ref()
+                .replace(PREVIEW_PLACEHOLDER, &preview)
+                .replace(PREVIEW_WINDOW_PLACEHOLDER, &config.preview_window)
+        })
+        .collect()
+}
+
+fn find_profile(command: &str) -> Option<&'static MenuProfile> {
+    let name = Path::new(command).file_name()?.to_str()?;
+    MENU_PROFILES.iter().find(|profile| profile.name == name)
+}
+
+/// Resolves the configured menu, falling back to the builtin menu if no
+/// supported menu command is installed.
+pub fn resolve_menu(config: &Config) -> Result<Menu> {
+    if config.menu.as_deref() == Some(BUILTIN_MENU) {
+        #[cfg(feature = "tui")]
+        return Ok(Menu::Builtin);
+        #[cfg(not(feature = "tui"))]
+        bail!("The builtin menu is not available\nRebuild git-smash with the tui feature");
+    }
+    if let Some(menu) = resolve_menu_command(config)? {
+        return Ok(Menu::Command(menu));
+    }
+    #[cfg(feature = "tui")]
+    return Ok(Menu::Builtin);
+    #[cfg(not(feature = "tui"))]
+    bail!("Can't find any supported fuzzy matcher or menu command\nPlease install fzf or configure one with smash.menu");
+}
+
+fn resolve_menu_command(config: &Config) -> Result<Option<MenuCommand>> {
+    if let Some(menu) = &config.menu {
+        let menu_args = config.menu_args.as_deref().map(split_args).transpose()?;
+        let bin = resolve_command(menu)?
+            .with_context(|| format!("Can't find configured menu command '{}'", menu))?;
+        let args = match (&menu_args, find_profile(menu)) {
+            (Some(args), _) => expand_args(args, config),
+            (None, Some(profile)) => expand_args(profile.args, config),
+            (None, None) => Vec::new(),
+        };
+        return Ok(Some(MenuCommand::new(bin, args)));
+    }
+
+    // The arguments of smash.menuArgs belong to smash.menu, a detected menu
+    // may not understand them
+    for profile in MENU_PROFILES.iter().filter(|profile| profile.detect) {
+        if let Some(bin) = resolve_command(profile.name)? {
+            let args = expand_args(profile.args, config);
+            return Ok(Some(MenuCommand::new(bin, args)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_args() -> Result<()> {
+        let args = split_args(r#"--ansi --preview "git show {1}" --bind='a:b c' x\ y"#)?;
+        assert_eq!(
+            args,
+            vec!["--ansi", "--preview", "git show {1}", "--bind=a:b c", "x y"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_args_empty_quotes() -> Result<()> {
+        assert_eq!(split_args("-p ''")?, vec!["-p", ""]);
+        assert!(split_args("'open").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_profile() {
+        assert_eq!(find_profile("/usr/bin/fzy").map(|e| e.name), Some("fzy"));
+        assert!(find_profile("fzf-tmux").is_none());
+    }
+}
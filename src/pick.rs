@@ -0,0 +1,197 @@
+use crate::config::Config;
+use crate::diff::{build_patch, map_to_old_line, parse_diff, Hunk};
+use crate::errors::*;
+use crate::git::*;
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+const HELP: &str = "y - smash this hunk
+n - do not smash this hunk
+a - smash this hunk and all later hunks in the file
+d - do not smash this hunk or any later hunks in the file
+q - quit; do not smash this hunk or any remaining ones
+? - print help";
+
+enum Answer {
+    Yes,
+    No,
+    All,
+    Done,
+    Quit,
+}
+
+/// Returns the commits the pre-image lines of a working tree hunk blame to
+/// with their line counts, most blamed first. The hunk is relative to the
+/// index, so its lines are mapped through the staged hunks to HEAD first.
+fn blame_hunk(
+    hunk: &Hunk,
+    file: &str,
+    staged: &[Hunk],
+    line_count: u32,
+    range: &str,
+    blame_options: &[&str],
+) -> Result<Vec<(String, u32)>> {
+    let (start, end) = match hunk.blame_range(line_count) {
+        Some(blame_range) => blame_range,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for line in (start..=end).filter_map(|e| map_to_old_line(staged, e)) {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    if ranges.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut commits: Vec<(String, u32)> = Vec::new();
+    for commit in git_blame(range, file, &ranges, blame_options)?
+        .into_iter()
+        .filter_map(|(_, commit)| commit)
+    {
+        match commits.iter_mut().find(|(e, _)| *e == commit) {
+            Some((_, count)) => *count += 1,
+            None => commits.push((commit, 1)),
+        }
+    }
+    commits.sort_by_key(|(_, count)| Reverse(*count));
+    Ok(commits)
+}
+
+fn describe_blame(
+    commits: &[(String, u32)],
+    subjects: &mut HashMap<String, String>,
+) -> Result<String> {
+    if commits.is_empty() {
+        return Ok("Blames to no commit in range".to_string());
+    }
+    let mut targets = Vec::new();
+    for (commit, count) in commits {
+        if !subjects.contains_key(commit) {
+            subjects.insert(commit.clone(), git_subject(commit)?);
+        }
+        targets.push(format!(
+            "{} {} ({} line{})",
+            commit.get(..7).unwrap_or(commit),
+            subjects[commit],
+            count,
+            if *count == 1 { "" } else { "s" }
+        ));
+    }
+    Ok(format!("Blames to {}", targets.join(", ")))
+}
+
+fn ask(tty: &mut File, prompt: &str) -> Result<Answer> {
+    loop {
+        write!(tty, "{} [y,n,a,d,q,?]? ", prompt)?;
+        let mut answer = String::new();
+        if BufReader::new(&*tty).read_line(&mut answer)? == 0 {
+            return Ok(Answer::Quit);
+        }
+        match answer.trim() {
+            "y" => return Ok(Answer::Yes),
+            "n" => return Ok(Answer::No),
+            "a" => return Ok(Answer::All),
+            "d" => return Ok(Answer::Done),
+            "q" => return Ok(Answer::Quit),
+            _ => writeln!(tty, "{}", HELP)?,
+        }
+    }
+}
+
+/// Lets the user pick the unstaged hunks to smash like git add -p, each hunk
+/// annotated with the commits it blames to, and stages the picked ones.
+pub fn pick_hunks(config: &Config, range: &str) -> Result<()> {
+    let files = parse_diff(&git_diff_worktree(0, &config.pathspecs)?)?;
+    if files.is_empty() {
+        return Ok(());
+    }
+    let staged = parse_diff(&git_diff_cached(0, &[])?)?;
+
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("failed to open terminal for picking hunks")?;
+
+    let mut subjects = HashMap::new();
+    let mut picked = Vec::new();
+    'files: for (file_index, file_diff) in files.iter().enumerate() {
+        let file = match (file_diff.is_modification(), &file_diff.old_file) {
+            (true, Some(file)) => file,
+            _ => continue,
+        };
+        let staged_hunks = staged
+            .iter()
+            .find(|e| e.new_file.as_ref() == Some(file))
+            .map_or(&[][..], |e| &e.hunks[..]);
+        let line_count = git_line_count("", file)?;
+        let blame_options = config.blame_options();
+
+        let mut all = false;
+        for (hunk_index, hunk) in file_diff.hunks.iter().enumerate() {
+            if all {
+                picked.push((file_index, hunk_index));
+                continue;
+            }
+
+            let commits = blame_hunk(hunk, file, staged_hunks, line_count, range, &blame_options)?;
+            writeln!(tty, "diff --git a/{0} b/{0}", file)?;
+            writeln!(tty, "{}", hunk.header(hunk.old_start, hunk.new_start))?;
+            for line in &hunk.lines {
+                writeln!(tty, "{}", line)?;
+            }
+            writeln!(tty, "{}", describe_blame(&commits, &mut subjects)?)?;
+
+            let prompt = format!(
+                "({}/{}) Smash this hunk",
+                hunk_index + 1,
+                file_diff.hunks.len()
+            );
+            match ask(&mut tty, &prompt)? {
+                Answer::Yes => picked.push((file_index, hunk_index)),
+                Answer::No => (),
+                Answer::All => {
+                    picked.push((file_index, hunk_index));
+                    all = true;
+                }
+                Answer::Done => continue 'files,
+                Answer::Quit => break 'files,
+            }
+        }
+    }
+
+    if picked.is_empty() {
+        return Ok(());
+    }
+    let patch = build_patch(&files, &picked, &HashSet::new());
+    git_apply_cached(&patch).context("failed to stage picked hunks")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_blame() -> Result<()> {
+        let mut subjects = HashMap::from([
+            ("a".repeat(40), "first".to_string()),
+            ("b".repeat(40), "second".to_string()),
+        ]);
+        assert_eq!(
+            describe_blame(&[], &mut subjects)?,
+            "Blames to no commit in range"
+        );
+        assert_eq!(
+            describe_blame(&[("a".repeat(40), 2), ("b".repeat(40), 1)], &mut subjects)?,
+            "Blames to aaaaaaa first (2 lines), bbbbbbb second (1 line)"
+        );
+        Ok(())
+    }
+}
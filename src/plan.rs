@@ -0,0 +1,105 @@
+use crate::config::{Config, FixupMode};
+use crate::errors::*;
+use crate::git::*;
+use crate::predict::{predict_conflict, PREDICT_GIT_VERSION};
+use crate::protect::find_protected_ref;
+use crate::rebase::rebase_todo;
+use crate::todo::{TodoAction, TodoCommit};
+
+use std::io::{self, Write};
+
+const NEW_COMMIT: &str = "(new)";
+
+/// Prints the fixup commits of the given mode and the rebase todo a smash
+/// into the targets would produce without writing anything.
+pub fn print_plan(targets: &[String], fixup_mode: FixupMode, config: &Config) -> Result<()> {
+    let mut stdout = io::stdout();
+
+    let mut fixups = Vec::new();
+    for target in targets {
+        let subject =
+            git_subject(target).with_context(|| format!("failed to get subject of {}", target))?;
+        let message = fixup_mode.to_subject(&subject);
+        writeln!(stdout, "Would commit: {}", message)?;
+        if let Some(reference) = find_protected_ref(target, config)? {
+            writeln!(
+                stdout,
+                "Would rewrite {} which is already contained in {}",
+                target, reference
+            )?;
+        }
+        fixups.push(TodoCommit::new(NEW_COMMIT, &message));
+    }
+
+    if !config.auto_rebase {
+        writeln!(stdout, "Would not rebase, autorebase is disabled")?;
+        return Ok(());
+    }
+
+    let oldest = match targets {
+        [target] => target.to_string(),
+        targets => {
+            git_merge_base_octopus(targets).context("failed to find the oldest target commit")?
+        }
+    };
+    let base = git_rebase_base(&oldest)?;
+    let mut commits = git_rebase_commits(&base)?;
+    commits.extend(fixups);
+
+    writeln!(
+        stdout,
+        "Would rebase onto {} with todo:",
+        base.as_deref().unwrap_or("--root")
+    )?;
+    let mut pending = 0;
+    for item in rebase_todo(&commits, &[NEW_COMMIT.to_string()], config) {
+        writeln!(stdout, "  {}", item)?;
+        if item.action != TodoAction::Pick && item.commit.commit != NEW_COMMIT {
+            pending += 1;
+        }
+    }
+    if pending > 0 {
+        writeln!(
+            stdout,
+            "{} already pending fixup commit(s) would be squashed as well",
+            pending
+        )?;
+    }
+
+    if let [target] = targets {
+        let git_version = git_version().context("failed to get git version")?;
+        if git_check_version(&git_version, PREDICT_GIT_VERSION, "conflict prediction").is_err() {
+            writeln!(
+                stdout,
+                "Conflict prediction unavailable, it requires git {}",
+                PREDICT_GIT_VERSION
+            )?;
+            return Ok(());
+        }
+        match predict_conflict(target, fixup_mode, config)? {
+            Some(conflict) => writeln!(stdout, "Would conflict at {}", conflict)?,
+            None => writeln!(stdout, "Would rebase without conflicts")?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the rebase todo that squashes the pending fixups into the target
+/// without writing anything.
+pub fn print_rebase_plan(target: &str, fixups: &[String], config: &Config) -> Result<()> {
+    let mut stdout = io::stdout();
+
+    let base = git_rebase_base(target)?;
+    let commits = git_rebase_commits(&base)?;
+    writeln!(
+        stdout,
+        "Would rebase onto {} with todo:",
+        base.as_deref().unwrap_or("--root")
+    )?;
+    for item in rebase_todo(&commits, fixups, config) {
+        writeln!(stdout, "  {}", item)?;
+    }
+
+    Ok(())
+}
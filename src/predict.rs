@@ -0,0 +1,113 @@
+use crate::config::{Config, FixupMode};
+use crate::errors::*;
+use crate::git::*;
+use crate::rebase::rebase_todo;
+use crate::todo::{TodoAction, TodoCommit, TodoItem};
+
+use std::{fmt, slice};
+
+/// Conflict prediction needs the write-tree mode of git merge-tree.
+pub const PREDICT_GIT_VERSION: &str = ">=2.38";
+
+pub struct Conflict {
+    pub item: TodoItem,
+    pub fixup: bool,
+    pub files: Vec<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.fixup {
+            true => write!(f, "{} (new) {}", self.item.action, self.item.commit.subject)?,
+            false => write!(f, "{}", self.item)?,
+        }
+        write!(f, " in {}", self.files.join(", "))
+    }
+}
+
+/// Simulates the rebase of a smash into the target in memory and returns the
+/// first todo item that would conflict.
+pub fn predict_conflict(
+    target: &str,
+    fixup_mode: FixupMode,
+    config: &Config,
+) -> Result<Option<Conflict>> {
+    let git_version = git_version().context("failed to get git version")?;
+    git_check_version(&git_version, PREDICT_GIT_VERSION, "conflict prediction")?;
+
+    // A reword fixup carries no changes that could conflict
+    if matches!(fixup_mode, FixupMode::Reword) {
+        return Ok(None);
+    }
+
+    let head = git_rev_parse("HEAD").context("failed to rev parse HEAD")?;
+    let index = match config.all_changes || !config.pathspecs.is_empty() {
+        true => git_write_smash_tree(config),
+        false => git_write_tree(),
+    }
+    .context("failed to write index tree")?;
+    let subject = fixup_mode.to_subject(&git_subject(target)?);
+    let fixup = git_commit_tree(&index, &head, &subject)
+        .context("failed to create temporary fixup commit")?;
+
+    let base = git_rebase_base(target)?;
+    let mut commits = git_rebase_commits(&base)?;
+    commits.push(TodoCommit::new(&fixup, &subject));
+
+    let todo = rebase_todo(&commits, slice::from_ref(&fixup), config);
+    match replay(base, todo, |_, tree, current| {
+        git_commit_tree(tree, current, "smash: predict")
+    })? {
+        Replay::Done(_) => Ok(None),
+        Replay::Conflict(mut conflict) => {
+            conflict.fixup = conflict.item.commit.commit == fixup;
+            Ok(Some(conflict))
+        }
+    }
+}
+
+pub enum Replay {
+    Done(String),
+    Conflict(Conflict),
+}
+
+/// Replays the todo onto the base without touching the index or working tree.
+/// Picks whose parent is already the current commit are reused as is, every
+/// other item is cherry-picked with merge-tree and recorded by the commit
+/// closure, which gets the item, the merged tree and the current commit.
+pub fn replay<F>(base: Option<String>, todo: Vec<TodoItem>, mut commit: F) -> Result<Replay>
+where
+    F: FnMut(&TodoItem, &str, &str) -> Result<String>,
+{
+    let mut current = base.as_deref().map(git_rev_parse).transpose()?;
+    for item in todo {
+        let rev = &item.commit.commit;
+        let parent = git_rev_parse(&format!("{}^", rev)).ok();
+        if item.action == TodoAction::Pick && parent == current {
+            current = Some(rev.to_string());
+            continue;
+        }
+        let (parent, head) = match (parent, &current) {
+            (Some(parent), Some(head)) => (parent, head),
+            _ => bail!("failed to replay root commit {}", rev),
+        };
+
+        // Give our side the parent of the picked commit, so the merge base
+        // equals a cherry-pick of it
+        let ours = git_commit_tree(&format!("{}^{{tree}}", head), &parent, "smash: replay")?;
+        let (tree, conflicts) =
+            git_merge_tree(&ours, rev).with_context(|| format!("failed to merge {}", rev))?;
+        if !conflicts.is_empty() {
+            return Ok(Replay::Conflict(Conflict {
+                item,
+                fixup: false,
+                files: conflicts,
+            }));
+        }
+        current = Some(commit(&item, &tree, head)?);
+    }
+
+    Ok(Replay::Done(
+        current.context("failed to replay empty todo")?,
+    ))
+}
@@ -0,0 +1,87 @@
+use crate::config::{Config, ProtectedAction};
+use crate::errors::*;
+use crate::git::*;
+
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+
+const DEFAULT_REMOTE: &str = "origin";
+
+/// The upstream of the checked out branch and the default branch of its
+/// remote, used if smash.protectedRefs is not set.
+fn default_protected_refs() -> Result<Vec<String>> {
+    let mut refs = Vec::new();
+    #[allow(clippy::literal_string_with_formatting_args)]
+    if let Some(upstream) = git_symbolic_full_name("@{upstream}")? {
+        refs.push(upstream);
+    }
+
+    let remote = match git_symbolic_head()? {
+        Some(branch) => git_upstream_remote(&branch)?,
+        None => None,
+    };
+    let remote = remote.unwrap_or_else(|| DEFAULT_REMOTE.to_string());
+    if let Some(default) = git_symbolic_full_name(&format!("refs/remotes/{}/HEAD", remote))? {
+        refs.push(default);
+    }
+    Ok(refs)
+}
+
+/// Returns the first protected ref that already contains the target.
+pub fn find_protected_ref(target: &str, config: &Config) -> Result<Option<String>> {
+    let refs = match &config.protected_refs {
+        Some(refs) => refs.split_whitespace().map(|e| e.to_string()).collect(),
+        None => default_protected_refs()?,
+    };
+    for reference in refs {
+        if !is_valid_git_rev(&reference)? {
+            continue;
+        }
+        if git_is_ancestor(target, &reference)? {
+            let name = reference
+                .strip_prefix("refs/remotes/")
+                .or_else(|| reference.strip_prefix("refs/heads/"))
+                .unwrap_or(&reference);
+            return Ok(Some(name.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    let mut tty = match OpenOptions::new().read(true).write(true).open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(_) => return Ok(false),
+    };
+    write!(tty, "{} [y/N] ", prompt)?;
+    let mut answer = String::new();
+    BufReader::new(&tty).read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Asks for confirmation or refuses to rewrite a target that is reachable
+/// from one of the protected refs, depending on smash.protectedAction.
+pub fn guard_protected(target: &str, config: &Config) -> Result<()> {
+    if config.protected_action == ProtectedAction::Allow {
+        return Ok(());
+    }
+    let reference = match find_protected_ref(target, config)? {
+        Some(reference) => reference,
+        None => return Ok(()),
+    };
+
+    let short = target.get(..7).unwrap_or(target);
+    if config.protected_action == ProtectedAction::Ask
+        && confirm(&format!(
+            "Commit {} is already contained in {}, rewrite it anyway?",
+            short, reference
+        ))?
+    {
+        return Ok(());
+    }
+    bail!(
+        "Refusing to rewrite {} which is already contained in {}\nPass --allow-protected or set smash.protectedAction=allow to rewrite it anyway",
+        short,
+        reference
+    )
+}
@@ -0,0 +1,201 @@
+use crate::config::{Config, RebaseBackend};
+use crate::errors::*;
+use crate::git::*;
+use crate::plan::print_rebase_plan;
+use crate::predict::{replay, Replay};
+use crate::protect::guard_protected;
+use crate::resume::{run_rebase, SMASH_TODO};
+use crate::todo::{
+    autosquash, autosquash_only, find_fixup_target, parse_fixup_subject, todo_list, TodoAction,
+    TodoCommit, TodoItem,
+};
+use crate::undo::record_smash_state;
+
+use std::fs;
+use std::io::{self, Write};
+
+/// Builds the rebase todo, either squashing every pending fixup or only the
+/// newly created ones depending on smash.squashPending.
+pub fn rebase_todo(commits: &[TodoCommit], fixups: &[String], config: &Config) -> Vec<TodoItem> {
+    match config.squash_pending {
+        true => autosquash(commits),
+        false => autosquash_only(commits, |e| fixups.contains(&e.commit)),
+    }
+}
+
+/// Squashes the new fixups into the target with the configured backend and
+/// restores the given index tree afterwards.
+pub fn rebase(target: &str, fixups: &[String], index: Option<&str>, config: &Config) -> Result<()> {
+    let memory = config.rebase_backend == RebaseBackend::Memory && !config.interactive;
+    if memory && rebase_in_memory(target, fixups, config)? {
+        return Ok(());
+    }
+
+    run_rebase(index, || {
+        let todo = match config.squash_pending {
+            true => None,
+            false => {
+                let base = git_rebase_base(target)?;
+                let todo = rebase_todo(&git_rebase_commits(&base)?, fixups, config);
+                // Our todo replaces the one git generated with its update-ref lines
+                let mut update_refs = Vec::new();
+                if config.update_refs {
+                    for branch in git_stacked_branches(target)? {
+                        update_refs.push((git_rev_parse(&branch)?, branch));
+                    }
+                }
+                let path = git_path(SMASH_TODO)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, todo_list(&todo, &update_refs))
+                    .context("failed to write rebase todo")?;
+                Some(path)
+            }
+        };
+        git_rebase(
+            target,
+            todo.as_deref(),
+            config.interactive,
+            &config.gpg_sign_option,
+            &config.verify_option,
+            &config.update_refs_option,
+        )
+    })
+}
+
+/// Squashes all pending fixup commits of the range with a single rebase from
+/// the parent of the oldest commit they refer to.
+pub fn rebase_pending(config: &Config, range: &str) -> Result<()> {
+    let commits =
+        git_range_commits(range).with_context(|| format!("failed to list commits of {}", range))?;
+
+    let mut fixups = Vec::new();
+    let mut oldest: Option<usize> = None;
+    for (index, commit) in commits.iter().enumerate() {
+        let (action, subject) = parse_fixup_subject(&commit.subject);
+        if action == TodoAction::Pick {
+            continue;
+        }
+        match find_fixup_target(subject, &commits[..index]) {
+            Some(target) => {
+                fixups.push(commit.commit.clone());
+                oldest = Some(oldest.map_or(target, |oldest| oldest.min(target)));
+            }
+            None => {
+                writeln!(
+                    io::stderr(),
+                    "No target found in {} for {}",
+                    range,
+                    commit.subject
+                )
+                .ok();
+            }
+        }
+    }
+
+    let target = match oldest {
+        Some(oldest) => &commits[oldest].commit,
+        None => {
+            writeln!(io::stderr(), "No pending fixup commits found in {}", range).ok();
+            return Ok(());
+        }
+    };
+
+    if config.dry_run {
+        return print_rebase_plan(target, &fixups, config);
+    }
+
+    guard_protected(target, config)?;
+    record_smash_state("rebase pending fixups")?;
+    rebase(target, &fixups, None, config)
+}
+
+fn fall_back(reason: &str) -> Result<bool> {
+    writeln!(
+        io::stderr(),
+        "In-memory rebase {}, falling back to git rebase",
+        reason
+    )
+    .ok();
+    Ok(false)
+}
+
+/// Rewrites the commits after the target with merge-tree and commit-tree and
+/// only moves HEAD at the end, returns false if the classic rebase has to
+/// take over.
+fn rebase_in_memory(target: &str, fixups: &[String], config: &Config) -> Result<bool> {
+    let head = git_rev_parse("HEAD").context("failed to rev parse HEAD")?;
+    let base = git_rebase_base(target)?;
+    let todo = rebase_todo(&git_rebase_commits(&base)?, fixups, config);
+    if todo.iter().any(|e| e.action == TodoAction::Squash) {
+        return fall_back("cannot edit squash messages");
+    }
+
+    let branches = match config.update_refs {
+        true => git_stacked_branches(target)?,
+        false => Vec::new(),
+    };
+
+    // Tracks the rewritten commit of every pick, fixups replace their target
+    let mut rewritten: Vec<(String, String)> = Vec::new();
+    let onto = base.clone().unwrap_or_else(|| "--root".to_string());
+    let new_head = match replay(base, todo, |item, tree, current| {
+        let commit = rewrite(item, tree, current, config)?;
+        match item.action {
+            TodoAction::Pick => rewritten.push((item.commit.commit.clone(), commit.clone())),
+            _ => match rewritten.iter_mut().find(|(_, new)| new == current) {
+                Some(entry) => entry.1 = commit.clone(),
+                None => rewritten.push((current.to_string(), commit.clone())),
+            },
+        }
+        Ok(commit)
+    })? {
+        Replay::Done(new_head) => new_head,
+        Replay::Conflict(conflict) => {
+            return fall_back(&format!("conflicts at {}", conflict));
+        }
+    };
+
+    // Index and working tree are only in sync if the final tree is unchanged
+    if git_rev_parse(&format!("{}^{{tree}}", new_head))?
+        != git_rev_parse(&format!("{}^{{tree}}", head))?
+    {
+        return fall_back("changes the final tree");
+    }
+
+    for branch in branches {
+        let tip = git_rev_parse(&branch)?;
+        if let Some((_, new)) = rewritten.iter().find(|(old, _)| *old == tip) {
+            git_update_ref(&branch, new, &format!("smash: rebase onto {}", onto))
+                .with_context(|| format!("failed to update {}", branch))?;
+        }
+    }
+    git_update_ref("HEAD", &new_head, &format!("smash: rebase onto {}", onto))
+        .context("failed to update HEAD")?;
+    Ok(true)
+}
+
+fn rewrite(item: &TodoItem, tree: &str, current: &str, config: &Config) -> Result<String> {
+    let rev = &item.commit.commit;
+    match item.action {
+        TodoAction::Pick => {
+            let info = git_commit_info(rev)?;
+            git_commit_tree_as(tree, Some(current), &info, &config.gpg_sign_option)
+        }
+        _ => {
+            // Fixups amend the current commit in place
+            let parent = git_rev_parse(&format!("{}^", current)).ok();
+            let mut info = git_commit_info(current)?;
+            if item.action == TodoAction::FixupC {
+                let message = git_commit_info(rev)?.message;
+                info.message = message
+                    .split_once("\n\n")
+                    .map(|(_, body)| body.to_string())
+                    .unwrap_or_default();
+            }
+            git_commit_tree_as(tree, parent.as_deref(), &info, &config.gpg_sign_option)
+        }
+    }
+    .with_context(|| format!("failed to rewrite {}", rev))
+}
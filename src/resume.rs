@@ -0,0 +1,123 @@
+use crate::errors::*;
+use crate::git::*;
+use crate::undo::{last_smash_state, restore_smash_state};
+
+use std::fs;
+use std::io::{self, Write};
+
+const SMASH_PROGRESS: &str = "smash/progress";
+/// Todo list given to the rebase if it only squashes the new fixups.
+pub const SMASH_TODO: &str = "smash/todo";
+
+const STOPPED_HINT: &str = "Smash stopped during the rebase\nResolve the conflicts and run git smash --continue\nTo restore the state before the smash run git smash --abort";
+
+struct Progress {
+    state: String,
+    /// Index tree to restore after the rebase and the HEAD it was rebased from
+    index: Option<(String, String)>,
+}
+
+/// Remembers the recorded smash state while its rebase is running so it can
+/// be continued or aborted later, together with the index tree to restore
+/// once the rebase is done.
+pub fn save_progress(index: Option<&str>) -> Result<()> {
+    let state = last_smash_state()?.context("failed to find recorded smash state")?;
+    let mut progress = format!("{}\n", state);
+    if let Some(tree) = index {
+        let head = git_rev_parse("HEAD").context("failed to rev parse HEAD")?;
+        progress.push_str(&format!("index {} {}\n", tree, head));
+    }
+    let path = git_path(SMASH_PROGRESS)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, progress).context("failed to save smash progress")
+}
+
+pub fn smash_in_progress() -> Result<bool> {
+    Ok(git_path(SMASH_PROGRESS)?.exists())
+}
+
+pub fn clear_progress() -> Result<()> {
+    for file in [SMASH_PROGRESS, SMASH_TODO] {
+        let path = git_path(file)?;
+        if path.exists() {
+            fs::remove_file(&path).context("failed to remove smash progress")?;
+        }
+    }
+    Ok(())
+}
+
+fn load_progress() -> Result<Progress> {
+    if !smash_in_progress()? {
+        bail!("No smash in progress");
+    }
+    let path = git_path(SMASH_PROGRESS)?;
+    let progress = fs::read_to_string(&path).context("failed to read smash progress")?;
+    let mut lines = progress.lines();
+    let state = lines.next().context("failed to read smash progress")?;
+    let index = lines
+        .find_map(|e| e.strip_prefix("index "))
+        .and_then(|e| e.split_once(' '))
+        .map(|(tree, head)| (tree.to_string(), head.to_string()));
+    Ok(Progress {
+        state: state.to_string(),
+        index,
+    })
+}
+
+/// Restores the saved index and clears the progress once the rebase is done.
+/// The autostash of the rebase gives staged changes back unstaged, they are
+/// staged again if the rebase left the tree unchanged.
+fn finish_progress() -> Result<()> {
+    if let Some((tree, head)) = load_progress()?.index {
+        let tree_of = |rev: &str| git_rev_parse(&format!("{}^{{tree}}", rev));
+        if tree_of("HEAD")? == tree_of(&head)? {
+            git_read_tree(&tree).context("failed to restore index")?;
+        }
+    }
+    clear_progress()
+}
+
+/// Runs the rebase of a smash and keeps its progress if it stops on conflicts.
+/// The given index tree is restored after the rebase.
+pub fn run_rebase<F>(index: Option<&str>, rebase: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    save_progress(index)?;
+    if let Err(err) = rebase() {
+        if git_rebase_in_progress()? {
+            bail!(STOPPED_HINT);
+        }
+        clear_progress()?;
+        return Err(err);
+    }
+    finish_progress()
+}
+
+/// Continues the rebase of a stopped smash.
+pub fn smash_continue() -> Result<()> {
+    load_progress()?;
+    if git_rebase_in_progress()? && git_rebase_resume("--continue").is_err() {
+        bail!(STOPPED_HINT);
+    }
+    finish_progress()
+}
+
+/// Aborts the rebase of a stopped smash and restores HEAD and index to the
+/// state before the smash, dropping its fixup commits.
+pub fn smash_abort() -> Result<()> {
+    let state = load_progress()?.state;
+    if last_smash_state()?.as_deref() != Some(state.as_str()) {
+        clear_progress()?;
+        bail!("The recorded smash state changed since the smash stopped\nUse git smash undo to restore the last recorded state");
+    }
+    if git_rebase_in_progress()? {
+        git_rebase_resume("--abort")?;
+    }
+    restore_smash_state(&state)?;
+    clear_progress()?;
+    writeln!(io::stdout(), "Aborted smash").ok();
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+
+const FIXUP_PREFIXES: [&str; 3] = ["fixup! ", "squash! ", "amend! "];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TodoAction {
+    Pick,
+    Fixup,
+    FixupC,
+    Squash,
+}
+
+impl fmt::Display for TodoAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pick => write!(f, "pick"),
+            Self::Fixup => write!(f, "fixup"),
+            Self::FixupC => write!(f, "fixup -C"),
+            Self::Squash => write!(f, "squash"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoCommit {
+    pub commit: String,
+    pub subject: String,
+}
+
+impl TodoCommit {
+    pub fn new(commit: &str, subject: &str) -> Self {
+        Self {
+            commit: commit.to_string(),
+            subject: subject.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TodoItem {
+    pub action: TodoAction,
+    pub commit: TodoCommit,
+}
+
+impl fmt::Display for TodoItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let commit = &self.commit.commit;
+        let short = commit.get(..7).unwrap_or(commit);
+        write!(f, "{} {} {}", self.action, short, self.commit.subject)
+    }
+}
+
+/// Returns the action autosquash applies to a commit with this subject and
+/// the subject with all fixup prefixes stripped.
+pub fn parse_fixup_subject(subject: &str) -> (TodoAction, &str) {
+    let mut action = TodoAction::Pick;
+    let mut rest = subject;
+    while let Some((prefix, stripped)) = FIXUP_PREFIXES
+        .iter()
+        .find_map(|prefix| rest.strip_prefix(prefix).map(|e| (*prefix, e)))
+    {
+        // The outermost prefix decides how the commit is squashed
+        if action == TodoAction::Pick {
+            action = match prefix {
+                "squash! " => TodoAction::Squash,
+                "amend! " => TodoAction::FixupC,
+                _ => TodoAction::Fixup,
+            };
+        }
+        rest = stripped;
+    }
+    (action, rest)
+}
+
+/// Finds the commit a fixup subject refers to among the given commits the
+/// same way git's autosquash does: by exact subject, by commit hash and
+/// finally by subject prefix.
+pub fn find_fixup_target(subject: &str, commits: &[TodoCommit]) -> Option<usize> {
+    commits
+        .iter()
+        .position(|e| e.subject == subject)
+        .or_else(|| {
+            if subject.len() < 4 || !subject.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            commits.iter().position(|e| e.commit.starts_with(subject))
+        })
+        .or_else(|| commits.iter().position(|e| e.subject.starts_with(subject)))
+}
+
+/// Returns the commit each fixup commit of the list refers to, skipping the
+/// other fixups so chains resolve to the original commit. Fixups without a
+/// target in the list map to None.
+pub fn resolve_fixup_targets(commits: &[TodoCommit]) -> Vec<(String, Option<String>)> {
+    let mut resolved = Vec::new();
+    let mut targets = Vec::new();
+    for commit in commits {
+        let (action, subject) = parse_fixup_subject(&commit.subject);
+        if action == TodoAction::Pick {
+            targets.push(commit.clone());
+            continue;
+        }
+        let target = find_fixup_target(subject, &targets).map(|e| targets[e].commit.clone());
+        resolved.push((commit.commit.clone(), target));
+    }
+    resolved
+}
+
+/// Rearranges the commits into the todo list of an autosquash rebase, the
+/// commits are expected in topological order starting with the oldest.
+pub fn autosquash(commits: &[TodoCommit]) -> Vec<TodoItem> {
+    autosquash_only(commits, |_| true)
+}
+
+/// Like autosquash but only moves the fixups selected by the filter, all
+/// other commits are picked where they are.
+pub fn autosquash_only<F>(commits: &[TodoCommit], filter: F) -> Vec<TodoItem>
+where
+    F: Fn(&TodoCommit) -> bool,
+{
+    let mut squashed: Vec<Vec<usize>> = vec![Vec::new(); commits.len()];
+    let mut picked = vec![true; commits.len()];
+
+    for (index, commit) in commits.iter().enumerate() {
+        let (action, subject) = parse_fixup_subject(&commit.subject);
+        if action == TodoAction::Pick || !filter(commit) {
+            continue;
+        }
+        if let Some(target) = find_fixup_target(subject, &commits[..index]) {
+            // Fixups of fixups are squashed into the root of the chain
+            let target = (0..commits.len())
+                .find(|e| picked[*e] && (*e == target || squashed[*e].contains(&target)))
+                .unwrap_or(target);
+            squashed[target].push(index);
+            picked[index] = false;
+        }
+    }
+
+    let mut todo = Vec::new();
+    for (index, commit) in commits.iter().enumerate() {
+        if !picked[index] {
+            continue;
+        }
+        todo.push(TodoItem {
+            action: TodoAction::Pick,
+            commit: commit.clone(),
+        });
+        for fixup in &squashed[index] {
+            let commit = &commits[*fixup];
+            todo.push(TodoItem {
+                action: parse_fixup_subject(&commit.subject).0,
+                commit: commit.clone(),
+            });
+        }
+    }
+    todo
+}
+
+/// Renders the todo list as rebase reads it, with full commit hashes. The
+/// refs given with the commit they point at are updated after the pick of
+/// that commit and everything squashed into it, like --update-refs does.
+pub fn todo_list(todo: &[TodoItem], update_refs: &[(String, String)]) -> String {
+    let mut list = String::new();
+    let mut group: Vec<&str> = Vec::new();
+    for (index, item) in todo.iter().enumerate() {
+        list.push_str(&format!(
+            "{} {} {}\n",
+            item.action, item.commit.commit, item.commit.subject
+        ));
+        group.push(&item.commit.commit);
+        if todo
+            .get(index + 1)
+            .is_some_and(|e| e.action != TodoAction::Pick)
+        {
+            continue;
+        }
+        for (_, name) in update_refs
+            .iter()
+            .filter(|(commit, _)| group.contains(&commit.as_str()))
+        {
+            list.push_str(&format!("update-ref {}\n", name));
+        }
+        group.clear();
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commits(subjects: &[&str]) -> Vec<TodoCommit> {
+        subjects
+            .iter()
+            .enumerate()
+            .map(|(i, subject)| TodoCommit::new(&format!("{}{:039}", i + 1, 0), subject))
+            .collect()
+    }
+
+    fn actions(todo: &[TodoItem]) -> Vec<String> {
+        todo.iter()
+            .map(|e| format!("{} {}", e.action, e.commit.subject))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_fixup_subject() {
+        assert_eq!(parse_fixup_subject("foo"), (TodoAction::Pick, "foo"));
+        assert_eq!(
+            parse_fixup_subject("fixup! fixup! foo"),
+            (TodoAction::Fixup, "foo")
+        );
+        assert_eq!(
+            parse_fixup_subject("squash! fixup! foo"),
+            (TodoAction::Squash, "foo")
+        );
+        assert_eq!(
+            parse_fixup_subject("amend! foo"),
+            (TodoAction::FixupC, "foo")
+        );
+    }
+
+    #[test]
+    fn test_autosquash_moves_fixups() {
+        let commits = commits(&["a", "b", "fixup! a", "c", "squash! b", "amend! a"]);
+        assert_eq!(
+            actions(&autosquash(&commits)),
+            vec![
+                "pick a",
+                "fixup fixup! a",
+                "fixup -C amend! a",
+                "pick b",
+                "squash squash! b",
+                "pick c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_autosquash_by_hash_and_prefix() {
+        let mut commits = commits(&["first commit", "second", "fixup! first", "x"]);
+        commits[3].subject = format!("fixup! {}", &commits[1].commit[..10]);
+        assert_eq!(
+            actions(&autosquash(&commits))[..4],
+            [
+                "pick first commit",
+                "fixup fixup! first",
+                "pick second",
+                format!("fixup fixup! {}", &commits[1].commit[..10]).as_str(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_autosquash_only_selected() {
+        let commits = commits(&["a", "fixup! a", "b", "fixup! a"]);
+        let new = commits[3].commit.clone();
+        assert_eq!(
+            actions(&autosquash_only(&commits, |e| e.commit == new)),
+            vec!["pick a", "fixup fixup! a", "pick fixup! a", "pick b"]
+        );
+    }
+
+    #[test]
+    fn test_autosquash_keeps_unmatched() {
+        let commits = commits(&["a", "fixup! unknown"]);
+        assert_eq!(
+            actions(&autosquash(&commits)),
+            vec!["pick a", "pick fixup! unknown"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_fixup_targets() {
+        let commits = vec![
+            TodoCommit::new("1111", "foo"),
+            TodoCommit::new("2222", "fixup! foo"),
+            TodoCommit::new("3333", "amend! fixup! foo"),
+            TodoCommit::new("4444", "bar"),
+            TodoCommit::new("5555", "squash! baz"),
+        ];
+        assert_eq!(
+            resolve_fixup_targets(&commits),
+            vec![
+                ("2222".to_string(), Some("1111".to_string())),
+                ("3333".to_string(), Some("1111".to_string())),
+                ("5555".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_todo_list_update_refs() {
+        let commits = commits(&["a", "b", "fixup! a", "c"]);
+        let refs = vec![
+            (commits[0].commit.clone(), "refs/heads/x".to_string()),
+            (commits[1].commit.clone(), "refs/heads/y".to_string()),
+        ];
+        let list = todo_list(&autosquash(&commits), &refs);
+        let lines = list
+            .lines()
+            .map(|e| e.split(' ').next().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec!["pick", "fixup", "update-ref", "pick", "update-ref", "pick"]
+        );
+        assert!(list.contains("update-ref refs/heads/x\npick"));
+    }
+}
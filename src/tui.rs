@@ -0,0 +1,391 @@
+use crate::config::Config;
+use crate::errors::*;
+
+use std::fs::{File, OpenOptions};
+use std::process::{Command, Stdio};
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Text};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+
+#[derive(Debug, PartialEq, Eq)]
+enum PreviewPosition {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct PreviewLayout {
+    position: PreviewPosition,
+    size: Constraint,
+    hidden: bool,
+}
+
+/// Parses the subset of the fzf --preview-window syntax that describes the
+/// position, size and visibility of the preview.
+fn parse_preview_window(spec: &str) -> PreviewLayout {
+    let mut layout = PreviewLayout {
+        position: PreviewPosition::Right,
+        size: Constraint::Percentage(50),
+        hidden: false,
+    };
+    for token in spec.split([':', ',']) {
+        match token {
+            "up" | "top" => layout.position = PreviewPosition::Up,
+            "down" | "bottom" => layout.position = PreviewPosition::Down,
+            "left" => layout.position = PreviewPosition::Left,
+            "right" => layout.position = PreviewPosition::Right,
+            "hidden" => layout.hidden = true,
+            "nohidden" => layout.hidden = false,
+            token => {
+                if let Some(Ok(percent)) = token.strip_suffix('%').map(str::parse::<u16>) {
+                    layout.size = Constraint::Percentage(percent.min(100));
+                } else if let Ok(length) = token.parse::<u16>() {
+                    layout.size = Constraint::Length(length);
+                }
+            }
+        }
+    }
+    layout
+}
+
+/// Scores a case-insensitive subsequence match of every whitespace separated
+/// term, favoring consecutive characters and matches at word boundaries.
+fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+    let mut score = 0;
+
+    for term in query.to_lowercase().split_whitespace() {
+        let mut position = 0;
+        let mut last: Option<usize> = None;
+        for c in term.chars() {
+            let index = position + text[position..].iter().position(|e| *e == c)?;
+            score += 1;
+            if last.is_some_and(|last| last + 1 == index) {
+                score += 5;
+            }
+            if index == 0 || !text[index - 1].is_alphanumeric() {
+                score += 3;
+            }
+            last = Some(index);
+            position = index + 1;
+        }
+    }
+
+    Some(score)
+}
+
+fn commit_of(target: &str) -> &str {
+    target.split(' ').next().unwrap_or_default()
+}
+
+fn colorize_preview(output: &str) -> Text<'static> {
+    output
+        .lines()
+        .map(|line| {
+            let style = if line.starts_with("commit ") {
+                Style::default().fg(Color::Yellow)
+            } else if line.starts_with("diff ") {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else if line.starts_with("@@") {
+                Style::default().fg(Color::Cyan)
+            } else if line.starts_with('+') {
+                Style::default().fg(Color::Green)
+            } else if line.starts_with('-') {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Line::styled(line.to_string(), style)
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+struct Picker<'a> {
+    targets: &'a [String],
+    ext_diff: Option<String>,
+    layout: PreviewLayout,
+    query: String,
+    matches: Vec<usize>,
+    list: ListState,
+    preview: Option<(usize, Text<'static>)>,
+    preview_scroll: u16,
+    preview_height: u16,
+}
+
+impl<'a> Picker<'a> {
+    fn new(targets: &'a [String], config: &Config) -> Self {
+        let mut picker = Self {
+            targets,
+            ext_diff: config.ext_diff.clone(),
+            layout: parse_preview_window(&config.preview_window),
+            query: String::new(),
+            matches: Vec::new(),
+            list: ListState::default(),
+            preview: None,
+            preview_scroll: 0,
+            preview_height: 0,
+        };
+        picker.filter();
+        picker
+    }
+
+    fn filter(&mut self) {
+        let mut matches = self
+            .targets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, target)| fuzzy_score(&self.query, target).map(|e| (index, e)))
+            .collect::<Vec<_>>();
+        if !self.query.is_empty() {
+            matches.sort_by_key(|(_, score)| -score);
+        }
+        self.matches = matches.into_iter().map(|(index, _)| index).collect();
+        self.list.select((!self.matches.is_empty()).then_some(0));
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.list
+            .selected()
+            .and_then(|index| self.matches.get(index))
+            .copied()
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let last = self.matches.len() - 1;
+        let index = self.list.selected().unwrap_or_default();
+        self.list
+            .select(Some(index.saturating_add_signed(offset).min(last)));
+    }
+
+    fn update_preview(&mut self) -> Result<()> {
+        let selected = match self.selected() {
+            Some(selected) => selected,
+            None => {
+                self.preview = None;
+                return Ok(());
+            }
+        };
+        if matches!(self.preview, Some((index, _)) if index == selected) {
+            return Ok(());
+        }
+
+        let mut args = vec!["--no-pager", "show", "--stat", "--patch", "--color=never"];
+        if let Some(ext_diff) = &self.ext_diff {
+            args.push(ext_diff);
+        }
+        args.push(commit_of(&self.targets[selected]));
+        let output = Command::new("git")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .args(&args)
+            .output()?;
+        self.preview = Some((
+            selected,
+            colorize_preview(&String::from_utf8_lossy(&output.stdout)),
+        ));
+        self.preview_scroll = 0;
+        Ok(())
+    }
+
+    fn areas(&self, area: Rect) -> (Rect, Rect, Option<Rect>) {
+        let [main, prompt] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(area);
+        if self.layout.hidden {
+            return (main, prompt, None);
+        }
+
+        let direction = match self.layout.position {
+            PreviewPosition::Up | PreviewPosition::Down => Direction::Vertical,
+            PreviewPosition::Left | PreviewPosition::Right => Direction::Horizontal,
+        };
+        let preview_first = matches!(
+            self.layout.position,
+            PreviewPosition::Up | PreviewPosition::Left
+        );
+        let constraints = match preview_first {
+            true => [self.layout.size, Constraint::Min(1)],
+            false => [Constraint::Min(1), self.layout.size],
+        };
+        let split = Layout::default()
+            .direction(direction)
+            .constraints(constraints)
+            .split(main);
+        match preview_first {
+            true => (split[1], prompt, Some(split[0])),
+            false => (split[0], prompt, Some(split[1])),
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let (list_area, prompt_area, preview_area) = self.areas(frame.area());
+
+        let items = self
+            .matches
+            .iter()
+            .map(|index| ListItem::new(self.targets[*index].as_str()))
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        let prompt = format!(
+            "{}/{} > {}",
+            self.matches.len(),
+            self.targets.len(),
+            self.query
+        );
+        let cursor = prompt.chars().count() as u16;
+        frame.render_widget(Paragraph::new(prompt), prompt_area);
+        frame.set_cursor_position((prompt_area.x + cursor, prompt_area.y));
+
+        if let Some(preview_area) = preview_area {
+            let block = Block::default().borders(Borders::ALL);
+            self.preview_height = block.inner(preview_area).height;
+            let text = self
+                .preview
+                .as_ref()
+                .map(|(_, text)| text.clone())
+                .unwrap_or_default();
+            let preview = Paragraph::new(text)
+                .block(block)
+                .scroll((self.preview_scroll, 0));
+            frame.render_widget(preview, preview_area);
+        }
+    }
+
+    /// Handles a key press and returns the final selection once the picker
+    /// is done, None as selection means the user aborted.
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Option<String>> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Some(None),
+            KeyCode::Char('c' | 'g' | 'q') if ctrl => return Some(None),
+            KeyCode::Enter => return Some(self.selected().map(|e| self.targets[e].clone())),
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Char('p' | 'k') if ctrl => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Char('n' | 'j') if ctrl => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Char('f') if ctrl => {
+                self.preview_scroll = self.preview_scroll.saturating_add(self.preview_height);
+            }
+            KeyCode::Char('b') if ctrl => {
+                self.preview_scroll = self.preview_scroll.saturating_sub(self.preview_height);
+            }
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.filter();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.filter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => (),
+        }
+        None
+    }
+
+    fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<File>>) -> Result<Option<String>> {
+        loop {
+            if !self.layout.hidden {
+                self.update_preview()?;
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(selection) = self.handle_key(key) {
+                    return Ok(selection);
+                }
+            }
+        }
+    }
+}
+
+/// Lets the user pick one of the targets in a terminal menu drawn on the
+/// controlling tty and returns the selected line.
+pub fn select_target_builtin(targets: &[String], config: &Config) -> Result<Option<String>> {
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .context("failed to open terminal for the builtin menu")?;
+
+    enable_raw_mode()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(tty))?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+
+    let result = Picker::new(targets, config).run(&mut terminal);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_preview_window() {
+        assert_eq!(
+            parse_preview_window("right"),
+            PreviewLayout {
+                position: PreviewPosition::Right,
+                size: Constraint::Percentage(50),
+                hidden: false,
+            }
+        );
+        assert_eq!(
+            parse_preview_window("up:40%"),
+            PreviewLayout {
+                position: PreviewPosition::Up,
+                size: Constraint::Percentage(40),
+                hidden: false,
+            }
+        );
+        assert_eq!(
+            parse_preview_window("bottom,20,hidden"),
+            PreviewLayout {
+                position: PreviewPosition::Down,
+                size: Constraint::Length(20),
+                hidden: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("", "abc"), Some(0));
+        assert!(fuzzy_score("xyz", "abc def").is_none());
+        assert!(fuzzy_score("ad", "abc def").is_some());
+        assert!(fuzzy_score("abc", "abc def") > fuzzy_score("abd", "abc def"));
+        assert!(fuzzy_score("DEF abc", "abc def").is_some());
+        assert!(fuzzy_score("abc zzz", "abc def").is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::errors::*;
+use crate::git::*;
+
+use std::io::{self, Write};
+
+const SMASH_REF: &str = "refs/smash/last";
+const DETACHED_HEAD: &str = "HEAD";
+
+#[derive(Debug, PartialEq, Eq)]
+struct SmashState {
+    head: String,
+    branch: Option<String>,
+    index: String,
+}
+
+impl SmashState {
+    fn to_message(&self, description: &str) -> String {
+        format!(
+            "{}\n\nhead {}\nbranch {}\nindex {}\n",
+            description,
+            self.head,
+            self.branch.as_deref().unwrap_or(DETACHED_HEAD),
+            self.index
+        )
+    }
+
+    fn parse(message: &str) -> Result<Self> {
+        let field = |name: &str| -> Result<String> {
+            message
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+                .map(|e| e.to_string())
+                .with_context(|| format!("failed to get {} of recorded smash state", name))
+        };
+        let branch = field("branch")?;
+        Ok(Self {
+            head: field("head")?,
+            branch: (branch != DETACHED_HEAD).then_some(branch),
+            index: field("index")?,
+        })
+    }
+}
+
+/// Records HEAD, the checked out branch and the index under the smash ref
+/// so the smash can be undone later.
+pub fn record_smash_state(description: &str) -> Result<()> {
+    let state = SmashState {
+        head: git_rev_parse("HEAD").context("failed to rev parse HEAD")?,
+        branch: git_symbolic_head()?,
+        index: git_write_tree().context("failed to write index tree")?,
+    };
+    let commit = git_commit_tree(&state.index, &state.head, &state.to_message(description))
+        .context("failed to record smash state")?;
+    git_update_ref(SMASH_REF, &commit, &format!("smash: {}", description))
+        .context("failed to update smash ref")
+}
+
+/// Returns the commit holding the state recorded before the last smash.
+pub fn last_smash_state() -> Result<Option<String>> {
+    if !is_valid_git_rev(SMASH_REF)? {
+        return Ok(None);
+    }
+    git_rev_parse(SMASH_REF).map(Some)
+}
+
+/// Restores branch, HEAD and index to the state recorded before the last
+/// smash, the working tree is left untouched.
+pub fn undo() -> Result<()> {
+    let state = match last_smash_state()? {
+        Some(state) => state,
+        None => bail!("No smash recorded that could be undone"),
+    };
+    if git_rebase_in_progress()? {
+        bail!("A rebase is in progress\nFinish it or run git rebase --abort first");
+    }
+    restore_smash_state(&state)
+}
+
+/// Restores branch, HEAD and index to the given recorded smash state.
+pub fn restore_smash_state(state: &str) -> Result<()> {
+    let state = SmashState::parse(&git_commit_message(state)?)?;
+    let branch = git_symbolic_head()?;
+    if branch != state.branch {
+        bail!(
+            "The last smash happened on {} but {} is checked out",
+            state.branch.as_deref().unwrap_or("a detached HEAD"),
+            branch.as_deref().unwrap_or("a detached HEAD")
+        );
+    }
+
+    git_reset_soft(&state.head, "smash: undo").context("failed to restore HEAD")?;
+    git_read_tree(&state.index).context("failed to restore index")?;
+
+    writeln!(
+        io::stdout(),
+        "Restored {} to {}",
+        branch.as_deref().unwrap_or(DETACHED_HEAD),
+        state.head
+    )
+    .ok();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smash_state_roundtrip() -> Result<()> {
+        let state = SmashState {
+            head: "1111".to_string(),
+            branch: Some("refs/heads/main".to_string()),
+            index: "2222".to_string(),
+        };
+        let message = state.to_message("smash into 3333");
+        assert!(message.starts_with("smash into 3333\n\n"));
+        assert_eq!(SmashState::parse(&message)?, state);
+        Ok(())
+    }
+
+    #[test]
+    fn test_smash_state_detached() -> Result<()> {
+        let state = SmashState {
+            head: "1111".to_string(),
+            branch: None,
+            index: "2222".to_string(),
+        };
+        assert_eq!(SmashState::parse(&state.to_message("absorb"))?, state);
+        assert!(SmashState::parse("absorb\n\nhead 1111\n").is_err());
+        Ok(())
+    }
+}